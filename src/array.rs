@@ -0,0 +1,411 @@
+//! Output to fixed-capacity buffers, which do not require `alloc`
+
+use crate::{
+    format_to_with, utf8::Utf8Decoder, BinSink, FormatOptions, FormatToError, Rollback,
+    SliceTooSmall, Value,
+};
+
+/// Fixed-capacity buffer on stack, which implements BinSink.
+///
+/// Like `SliceSink`, it fails if data does not fit, but tracks its length itself.
+///
+/// ```rust
+/// use formatf::{ArrayBuf, format_to, Value};
+/// let mut buf = ArrayBuf::<16>::new();
+/// format_to(b"%s=%d", &[Value::String(b"x"), Value::Int(1)], &mut buf).unwrap();
+/// assert_eq!(buf.as_str(), Ok("x=1"));
+/// ```
+#[derive(Clone)]
+pub struct ArrayBuf<const N: usize> {
+    buf: [u8; N],
+    len: usize,
+}
+
+impl<const N: usize> ArrayBuf<N> {
+    /// Creates empty buffer
+    pub const fn new() -> Self {
+        ArrayBuf {
+            buf: [0; N],
+            len: 0,
+        }
+    }
+
+    /// Returns written data
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+
+    /// Returns written data, if it is valid UTF-8
+    pub fn as_str(&self) -> Result<&str, core::str::Utf8Error> {
+        core::str::from_utf8(self.as_bytes())
+    }
+
+    /// Returns number of bytes written
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns `true` if nothing was written
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns number of bytes which can be written to empty buffer, i.e. `N`
+    pub fn capacity(&self) -> usize {
+        N
+    }
+
+    /// Removes all data
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    /// Reserves space for `len` bytes at the end of data
+    fn extend(&mut self, len: usize) -> Result<&mut [u8], SliceTooSmall> {
+        if N - self.len < len {
+            return Err(SliceTooSmall);
+        }
+        let start = self.len;
+        self.len += len;
+        Ok(&mut self.buf[start..self.len])
+    }
+}
+
+impl<const N: usize> Default for ArrayBuf<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> core::fmt::Debug for ArrayBuf<N> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_tuple("ArrayBuf").field(&self.as_bytes()).finish()
+    }
+}
+
+impl<const N: usize> BinSink for ArrayBuf<N> {
+    type Err = SliceTooSmall;
+
+    fn put(&mut self, data: &[u8]) -> Result<(), Self::Err> {
+        self.extend(data.len())?.copy_from_slice(data);
+        Ok(())
+    }
+
+    fn put_repeat(&mut self, byte: u8, count: usize) -> Result<(), Self::Err> {
+        self.extend(count)?.fill(byte);
+        Ok(())
+    }
+}
+
+impl<const N: usize> Rollback for ArrayBuf<N> {
+    type Checkpoint = usize;
+
+    fn checkpoint(&self) -> usize {
+        self.len
+    }
+
+    fn rollback(&mut self, checkpoint: usize) {
+        self.len = core::cmp::min(self.len, checkpoint);
+    }
+}
+
+/// Formats `template` with given `args` to new [`ArrayBuf`] of capacity `N`
+///
+/// ```rust
+/// use formatf::{format_array, Value};
+/// let buf = format_array::<8>(b"%03d", &[Value::Int(7)]).unwrap();
+/// assert_eq!(buf.as_bytes(), b"007");
+/// assert!(format_array::<2>(b"%03d", &[Value::Int(7)]).is_err());
+/// ```
+///
+/// [`ArrayBuf`]: ./struct.ArrayBuf.html
+pub fn format_array<const N: usize>(
+    template: &[u8],
+    args: &[Value],
+) -> Result<ArrayBuf<N>, FormatToError<SliceTooSmall>> {
+    format_array_with(template, args, &FormatOptions::default())
+}
+
+/// Same as [`format_array`], but uses given `options`
+///
+/// [`format_array`]: ./fn.format_array.html
+pub fn format_array_with<const N: usize>(
+    template: &[u8],
+    args: &[Value],
+    options: &FormatOptions,
+) -> Result<ArrayBuf<N>, FormatToError<SliceTooSmall>> {
+    let mut buf = ArrayBuf::new();
+    format_to_with(template, args, &mut buf, options)?;
+    Ok(buf)
+}
+
+/// Error returned by [`FixedStringSink`]
+///
+/// [`FixedStringSink`]: ./struct.FixedStringSink.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FixedStringError {
+    /// Data does not fit into string
+    Full,
+    /// Output is not valid UTF-8.
+    ///
+    /// Character split between chunks (e.g. between string argument and padding) is
+    /// accepted, as long as it is complete.
+    InvalidUtf8,
+}
+
+impl core::fmt::Display for FixedStringError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            FixedStringError::Full => f.write_str("string is full"),
+            FixedStringError::InvalidUtf8 => f.write_str("output is not valid UTF-8"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for FixedStringError {}
+
+/// String with fixed capacity, which can be written to with [`FixedStringSink`].
+///
+/// Implemented for `heapless::String` and `arrayvec::ArrayString`, if corresponding
+/// features are enabled.
+///
+/// [`FixedStringSink`]: ./struct.FixedStringSink.html
+pub trait FixedString {
+    /// Appends `s`, failing if it does not fit
+    fn try_push(&mut self, s: &str) -> Result<(), FixedStringError>;
+    /// Returns contents of string
+    fn as_str(&self) -> &str;
+    /// Shortens string to `len` bytes
+    fn truncate(&mut self, len: usize);
+}
+
+/// Helper struct that implements BinSink via appending bytes to fixed-capacity string.
+/// This Sink fails if data does not fit or is not valid UTF-8.
+///
+/// Character may be split between several chunks, so [`finish`] should be called after
+/// formatting to check that output does not end with incomplete character.
+///
+/// ```rust
+/// # #[cfg(feature = "heapless")]
+/// # {
+/// use formatf::{format_to, FixedStringSink, Value};
+/// let mut s = heapless::String::<8>::new();
+/// let mut sink = FixedStringSink::new(&mut s);
+/// format_to(b"%s=%d", &[Value::String("ñ".as_bytes()), Value::Int(1)], &mut sink).unwrap();
+/// sink.finish().unwrap();
+/// assert_eq!(s.as_str(), "ñ=1");
+/// # }
+/// ```
+///
+/// [`finish`]: #method.finish
+pub struct FixedStringSink<'a, S> {
+    string: &'a mut S,
+    decoder: Utf8Decoder,
+}
+
+impl<'a, S: FixedString> FixedStringSink<'a, S> {
+    /// Creates sink which appends to `string`
+    pub fn new(string: &'a mut S) -> Self {
+        FixedStringSink {
+            string,
+            decoder: Default::default(),
+        }
+    }
+
+    /// Checks that data does not end with incomplete character
+    pub fn finish(self) -> Result<(), FixedStringError> {
+        if self.decoder.has_pending() {
+            return Err(FixedStringError::InvalidUtf8);
+        }
+        Ok(())
+    }
+}
+
+impl<S: FixedString> BinSink for FixedStringSink<'_, S> {
+    type Err = FixedStringError;
+
+    fn put(&mut self, data: &[u8]) -> Result<(), Self::Err> {
+        let string = &mut *self.string;
+        self.decoder.decode(data, &mut |piece| match piece {
+            Ok(s) => string.try_push(s),
+            Err(_) => Err(FixedStringError::InvalidUtf8),
+        })
+    }
+}
+
+impl<S: FixedString> Rollback for FixedStringSink<'_, S> {
+    type Checkpoint = usize;
+
+    fn checkpoint(&self) -> usize {
+        self.string.as_str().len()
+    }
+
+    /// Truncates string. Incomplete character at the end of data is discarded too
+    fn rollback(&mut self, checkpoint: usize) {
+        self.string.truncate(checkpoint);
+        self.decoder = Default::default();
+    }
+}
+
+#[cfg(feature = "heapless")]
+impl<const N: usize> BinSink for heapless::Vec<u8, N> {
+    type Err = SliceTooSmall;
+
+    fn put(&mut self, data: &[u8]) -> Result<(), Self::Err> {
+        self.extend_from_slice(data).map_err(|()| SliceTooSmall)
+    }
+
+    fn put_repeat(&mut self, byte: u8, count: usize) -> Result<(), Self::Err> {
+        let len = self.len().checked_add(count).ok_or(SliceTooSmall)?;
+        self.resize(len, byte).map_err(|()| SliceTooSmall)
+    }
+}
+
+#[cfg(feature = "heapless")]
+impl<const N: usize> Rollback for heapless::Vec<u8, N> {
+    type Checkpoint = usize;
+
+    fn checkpoint(&self) -> usize {
+        self.len()
+    }
+
+    fn rollback(&mut self, checkpoint: usize) {
+        self.truncate(checkpoint);
+    }
+}
+
+#[cfg(feature = "heapless")]
+impl<const N: usize> FixedString for heapless::String<N> {
+    fn try_push(&mut self, s: &str) -> Result<(), FixedStringError> {
+        self.push_str(s).map_err(|()| FixedStringError::Full)
+    }
+
+    fn as_str(&self) -> &str {
+        self
+    }
+
+    fn truncate(&mut self, len: usize) {
+        heapless::String::truncate(self, len);
+    }
+}
+
+#[cfg(feature = "arrayvec")]
+impl<const N: usize> BinSink for arrayvec::ArrayVec<u8, N> {
+    type Err = SliceTooSmall;
+
+    fn put(&mut self, data: &[u8]) -> Result<(), Self::Err> {
+        self.try_extend_from_slice(data).map_err(|_| SliceTooSmall)
+    }
+
+    fn put_repeat(&mut self, byte: u8, count: usize) -> Result<(), Self::Err> {
+        if self.remaining_capacity() < count {
+            return Err(SliceTooSmall);
+        }
+        self.extend(core::iter::repeat(byte).take(count));
+        Ok(())
+    }
+}
+
+#[cfg(feature = "arrayvec")]
+impl<const N: usize> Rollback for arrayvec::ArrayVec<u8, N> {
+    type Checkpoint = usize;
+
+    fn checkpoint(&self) -> usize {
+        self.len()
+    }
+
+    fn rollback(&mut self, checkpoint: usize) {
+        self.truncate(checkpoint);
+    }
+}
+
+#[cfg(feature = "arrayvec")]
+impl<const N: usize> FixedString for arrayvec::ArrayString<N> {
+    fn try_push(&mut self, s: &str) -> Result<(), FixedStringError> {
+        self.try_push_str(s).map_err(|_| FixedStringError::Full)
+    }
+
+    fn as_str(&self) -> &str {
+        self
+    }
+
+    fn truncate(&mut self, len: usize) {
+        arrayvec::ArrayString::truncate(self, len);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format_to;
+
+    #[test]
+    fn array_buf() {
+        let args = [Value::String("añb".as_bytes()), Value::Int(-5)];
+        let buf = format_array::<16>(b"%s|%4d", &args).unwrap();
+        assert_eq!(buf.as_str(), Ok("añb|  -5"));
+        assert_eq!((buf.len(), buf.capacity()), (9, 16));
+
+        let mut buf = format_array::<8>(b"%.2s", &args[..1]).unwrap();
+        assert!(buf.as_str().is_err());
+        buf.clear();
+        let res = format_to(b"%s|%4d", &args, &mut buf);
+        assert!(matches!(res, Err(FormatToError::Sink(SliceTooSmall, 7))));
+        assert_eq!(buf.as_bytes(), "añb|  ".as_bytes());
+    }
+
+    #[test]
+    #[cfg(feature = "heapless")]
+    fn heapless() {
+        let args = [Value::String(b"ab"), Value::Int(7)];
+        let mut vec = heapless::Vec::<u8, 6>::new();
+        format_to(b"%s%3d", &args, &mut vec).unwrap();
+        assert_eq!(vec.as_slice(), b"ab  7");
+        assert!(format_to(b"%s%3d", &args, &mut vec).is_err());
+
+        let mut s = heapless::String::<8>::new();
+        let mut sink = FixedStringSink::new(&mut s);
+        format_to(b"%s=%d", &args, &mut sink).unwrap();
+        let res = format_to(b"%s", &[Value::String(b"\xff")], &mut sink);
+        assert!(matches!(
+            res,
+            Err(FormatToError::Sink(FixedStringError::InvalidUtf8, 0))
+        ));
+        assert_eq!(s.as_str(), "ab=7");
+
+        // character split between chunks
+        let mut s = heapless::String::<8>::new();
+        let mut sink = FixedStringSink::new(&mut s);
+        sink.put(b"\xc3").unwrap();
+        sink.put(b"\xb1\xe2\x82").unwrap();
+        assert_eq!(sink.finish(), Err(FixedStringError::InvalidUtf8));
+        assert_eq!(s.as_str(), "ñ");
+    }
+
+    #[test]
+    #[cfg(feature = "arrayvec")]
+    fn arrayvec() {
+        let args = [Value::String(b"ab"), Value::Int(7)];
+        let mut vec = arrayvec::ArrayVec::<u8, 6>::new();
+        format_to(b"%s%3d", &args, &mut vec).unwrap();
+        assert_eq!(vec.as_slice(), b"ab  7");
+        assert!(vec.put_repeat(b'-', 2).is_err());
+        vec.put_repeat(b'-', 1).unwrap();
+        assert_eq!(vec.as_slice(), b"ab  7-");
+
+        let mut s = arrayvec::ArrayString::<4>::new();
+        let mut sink = FixedStringSink::new(&mut s);
+        let res = format_to(b"%s=%d!", &args, &mut sink);
+        assert!(matches!(
+            res,
+            Err(FormatToError::Sink(FixedStringError::Full, 4))
+        ));
+        let res = crate::format_to_atomic(b"%s", &args, &mut sink, &FormatOptions::default());
+        assert!(matches!(
+            res,
+            Err(FormatToError::Sink(FixedStringError::Full, 0))
+        ));
+        assert_eq!(s.as_str(), "ab=7");
+    }
+}
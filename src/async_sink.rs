@@ -0,0 +1,379 @@
+//! Formatting to asynchronous sinks
+
+use crate::{format, BinSink, FormatOptions, FormatToError, Value};
+use core::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+/// Size of stack buffer used by [`format_to_async`]
+///
+/// [`format_to_async`]: ./fn.format_to_async.html
+pub const ASYNC_BUF_LEN: usize = 256;
+
+/// Asynchronous version of [`BinSink`]
+///
+/// [`BinSink`]: ./trait.BinSink.html
+pub trait AsyncBinSink {
+    type Err;
+    /// Attempts to process beginning of `data`, returning number of processed bytes.
+    ///
+    /// Unless `data` is empty, at least one byte must be processed.
+    fn poll_put(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        data: &[u8],
+    ) -> Poll<Result<usize, Self::Err>>;
+
+    /// Attempts to pass all processed data to underlying writer.
+    ///
+    /// Default implementation does nothing.
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Err>> {
+        let _ = cx;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S: AsyncBinSink + Unpin + ?Sized> AsyncBinSink for &mut S {
+    type Err = S::Err;
+
+    fn poll_put(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        data: &[u8],
+    ) -> Poll<Result<usize, Self::Err>> {
+        Pin::new(&mut **self).poll_put(cx, data)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Err>> {
+        Pin::new(&mut **self).poll_flush(cx)
+    }
+}
+
+/// Future which passes whole `data` to `sink`
+struct PutAll<'a, S: ?Sized> {
+    sink: &'a mut S,
+    data: &'a [u8],
+}
+
+impl<S: AsyncBinSink + Unpin + ?Sized> Future for PutAll<'_, S> {
+    type Output = Result<(), S::Err>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = &mut *self;
+        while !this.data.is_empty() {
+            match Pin::new(&mut *this.sink).poll_put(cx, this.data) {
+                Poll::Ready(Ok(n)) => this.data = &this.data[n.min(this.data.len())..],
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Future which flushes `sink`
+struct Flush<'a, S: ?Sized>(&'a mut S);
+
+impl<S: AsyncBinSink + Unpin + ?Sized> Future for Flush<'_, S> {
+    type Output = Result<(), S::Err>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut *self.0).poll_flush(cx)
+    }
+}
+
+/// Returned by `Window` when its buffer is full
+struct WindowFull;
+
+/// Sink which collects output to `buf`, accepting as much of each chunk as fits
+struct Window<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+}
+
+impl BinSink for Window<'_> {
+    type Err = WindowFull;
+
+    fn put(&mut self, data: &[u8]) -> Result<(), Self::Err> {
+        let len = core::cmp::min(data.len(), self.buf.len() - self.len);
+        self.buf[self.len..self.len + len].copy_from_slice(&data[..len]);
+        self.len += len;
+        if len < data.len() {
+            return Err(WindowFull);
+        }
+        Ok(())
+    }
+}
+
+/// Formats `template` with given `args`, writing to given asynchronous `sink`
+///
+/// Output is rendered into stack buffer of [`ASYNC_BUF_LEN`] bytes, which is passed to
+/// `sink` when it is full. Then formatting is resumed from the item (literal or conversion
+/// specification) which did not fit, like [`FormatJob`] does, so each item is rendered
+/// again at most once per buffer, and no allocation is needed.
+///
+/// After the last write `sink` is flushed once, also when formatting fails.
+///
+/// Returns number of bytes written.
+///
+/// [`ASYNC_BUF_LEN`]: ./constant.ASYNC_BUF_LEN.html
+/// [`FormatJob`]: ./struct.FormatJob.html
+pub async fn format_to_async<S: AsyncBinSink + Unpin + ?Sized>(
+    template: &[u8],
+    args: &[Value<'_>],
+    sink: &mut S,
+) -> Result<usize, FormatToError<S::Err>> {
+    format_to_async_with(template, args, sink, &FormatOptions::default()).await
+}
+
+/// Same as [`format_to_async`], but uses given `options`
+///
+/// [`format_to_async`]: ./fn.format_to_async.html
+pub async fn format_to_async_with<S: AsyncBinSink + Unpin + ?Sized>(
+    template: &[u8],
+    args: &[Value<'_>],
+    sink: &mut S,
+    options: &FormatOptions<'_>,
+) -> Result<usize, FormatToError<S::Err>> {
+    let mut buf = [0u8; ASYNC_BUF_LEN];
+    let mut written = 0;
+    // limits and atomic mode are checked by first pass only
+    let mut from = None;
+    loop {
+        let mut window = Window {
+            buf: &mut buf,
+            len: 0,
+        };
+        let res = format::resume(template, args, &mut window, options, from);
+        let len = window.len;
+        PutAll {
+            sink: &mut *sink,
+            data: &buf[..len],
+        }
+        .await
+        .map_err(|e| FormatToError::Sink(e, written))?;
+        written += len;
+        let res = match res {
+            Ok(_) => Ok(written),
+            Err((err, mut point)) => match err.split_sink() {
+                Err((WindowFull, accepted)) => {
+                    // beginning of chunk which did not fit is written too
+                    point.offset += len - accepted;
+                    from = Some(point);
+                    continue;
+                }
+                // output before error was collected, since buffer was not full
+                Ok(err) => Err(err),
+            },
+        };
+        Flush(&mut *sink)
+            .await
+            .map_err(|e| FormatToError::Sink(e, written))?;
+        return res;
+    }
+}
+
+/// Helper struct that implements AsyncBinSink via delegation to `futures_io::AsyncWrite`
+#[cfg(feature = "futures-io")]
+pub struct FuturesIoSink<W>(pub W);
+
+#[cfg(feature = "futures-io")]
+impl<W: futures_io::AsyncWrite + Unpin> AsyncBinSink for FuturesIoSink<W> {
+    type Err = std::io::Error;
+
+    fn poll_put(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        data: &[u8],
+    ) -> Poll<Result<usize, Self::Err>> {
+        match Pin::new(&mut self.get_mut().0).poll_write(cx, data) {
+            Poll::Ready(Ok(0)) if !data.is_empty() => {
+                Poll::Ready(Err(std::io::ErrorKind::WriteZero.into()))
+            }
+            other => other,
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Err>> {
+        Pin::new(&mut self.get_mut().0).poll_flush(cx)
+    }
+}
+
+/// Helper struct that implements AsyncBinSink via delegation to `tokio::io::AsyncWrite`
+#[cfg(feature = "tokio")]
+pub struct TokioSink<W>(pub W);
+
+#[cfg(feature = "tokio")]
+impl<W: tokio::io::AsyncWrite + Unpin> AsyncBinSink for TokioSink<W> {
+    type Err = std::io::Error;
+
+    fn poll_put(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        data: &[u8],
+    ) -> Poll<Result<usize, Self::Err>> {
+        match Pin::new(&mut self.get_mut().0).poll_write(cx, data) {
+            Poll::Ready(Ok(0)) if !data.is_empty() => {
+                Poll::Ready(Err(std::io::ErrorKind::WriteZero.into()))
+            }
+            other => other,
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Err>> {
+        Pin::new(&mut self.get_mut().0).poll_flush(cx)
+    }
+}
+
+#[cfg(all(test, feature = "alloc"))]
+mod tests {
+    use super::*;
+
+    /// Sink which accepts at most 3 bytes per call, and is not ready on every second call
+    #[derive(Default)]
+    struct Slow {
+        data: Vec<u8>,
+        calls: usize,
+        /// Length of `data` at each flush
+        flushes: Vec<usize>,
+    }
+
+    impl AsyncBinSink for Slow {
+        type Err = ();
+
+        fn poll_put(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            data: &[u8],
+        ) -> Poll<Result<usize, ()>> {
+            self.calls += 1;
+            if self.calls % 2 == 0 {
+                cx.waker().wake_by_ref();
+                return Poll::Pending;
+            }
+            let len = data.len().min(3);
+            self.data.extend_from_slice(&data[..len]);
+            Poll::Ready(Ok(len))
+        }
+
+        fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), ()>> {
+            self.calls += 1;
+            if self.calls % 2 == 0 {
+                cx.waker().wake_by_ref();
+                return Poll::Pending;
+            }
+            let len = self.data.len();
+            self.flushes.push(len);
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    fn block_on<F: Future>(fut: F) -> F::Output {
+        use core::task::{RawWaker, RawWakerVTable, Waker};
+        fn noop_raw() -> RawWaker {
+            fn clone(_: *const ()) -> RawWaker {
+                noop_raw()
+            }
+            fn noop(_: *const ()) {}
+            static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, noop, noop, noop);
+            RawWaker::new(core::ptr::null(), &VTABLE)
+        }
+        // SAFETY: all functions of vtable do nothing, so they are trivially thread-safe
+        let waker = unsafe { Waker::from_raw(noop_raw()) };
+        let mut fut = core::pin::pin!(fut);
+        let mut cx = Context::from_waker(&waker);
+        loop {
+            if let Poll::Ready(res) = fut.as_mut().poll(&mut cx) {
+                return res;
+            }
+        }
+    }
+
+    #[test]
+    fn passes() {
+        let args = [Value::Int(42), Value::String(b"end")];
+        for fmt in [&b"%d|%s"[..], b"%300d|%s", b"%-600d|%s"] {
+            let mut sink = Slow::default();
+            let written = block_on(format_to_async(fmt, &args, &mut sink)).unwrap();
+            assert_eq!(sink.data, crate::format(fmt, &args).unwrap());
+            assert_eq!(written, sink.data.len());
+            assert_eq!(sink.flushes, [written]);
+        }
+
+        let mut sink = Slow::default();
+        let res = block_on(format_to_async(b"%300d|%d", &args, &mut sink));
+        assert!(matches!(res, Err(FormatToError::BadType)));
+        assert_eq!(sink.data.len(), 301);
+        assert_eq!(sink.flushes, [301]);
+    }
+
+    #[test]
+    fn resumes_items() {
+        // side effects of items happen once, even if their output spans several buffers
+        let warnings = core::cell::Cell::new(0);
+        let warn = |_, _| warnings.set(warnings.get() + 1);
+        let options = FormatOptions::new()
+            .inline_errors(true)
+            .excess_args(crate::ExcessArgsPolicy::Warn(&warn));
+        let args = [Value::String(&[b'x'; 1000]), Value::Int(1), Value::Int(2)];
+        let fmt = b"%s|%-300d";
+        let mut sink = Slow::default();
+        let written = block_on(format_to_async_with(fmt, &args, &mut sink, &options)).unwrap();
+        assert_eq!(sink.data, crate::format_with(fmt, &args, &options).unwrap());
+        assert_eq!(written, sink.data.len());
+        assert_eq!(warnings.get(), 2);
+    }
+
+    #[test]
+    #[cfg(feature = "futures-io")]
+    fn futures_io_duplex() {
+        use tokio::io::AsyncReadExt;
+        use tokio_util::compat::TokioAsyncWriteCompatExt;
+        const FMT: &[u8] = b"%s #%d: %500s\n";
+        const ARGS: [Value; 3] = [Value::String(b"record"), Value::Int(7), Value::String(b"x")];
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let output = rt.block_on(async {
+            let (writer, mut reader) = tokio::io::duplex(16);
+            let reading = tokio::spawn(async move {
+                let mut output = Vec::new();
+                reader.read_to_end(&mut output).await.unwrap();
+                output
+            });
+            let mut sink = FuturesIoSink(writer.compat_write());
+            let written = format_to_async(FMT, &ARGS, &mut sink).await.unwrap();
+            drop(sink);
+            (written, reading.await.unwrap())
+        });
+        assert_eq!(output.0, output.1.len());
+        assert_eq!(output.1, crate::format(FMT, &ARGS).unwrap());
+    }
+
+    #[test]
+    #[cfg(feature = "tokio")]
+    fn tokio_duplex() {
+        use tokio::io::AsyncReadExt;
+        const FMT: &[u8] = b"%s #%d: %500s\n";
+        const ARGS: [Value; 3] = [Value::String(b"record"), Value::Int(7), Value::String(b"x")];
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let output = rt.block_on(async {
+            let (writer, mut reader) = tokio::io::duplex(16);
+            let reading = tokio::spawn(async move {
+                let mut output = Vec::new();
+                reader.read_to_end(&mut output).await.unwrap();
+                output
+            });
+            let mut sink = TokioSink(writer);
+            let written = format_to_async(FMT, &ARGS, &mut sink).await.unwrap();
+            drop(sink);
+            (written, reading.await.unwrap())
+        });
+        assert_eq!(output.0, output.1.len());
+        assert_eq!(output.1, crate::format(FMT, &ARGS).unwrap());
+    }
+}
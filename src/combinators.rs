@@ -0,0 +1,376 @@
+//! Sinks which combine other sinks
+
+use crate::{BinSink, Rollback};
+
+/// Error returned by [`Tee`]
+///
+/// [`Tee`]: ./struct.Tee.html
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TeeError<A, B> {
+    /// First sink failed
+    First(A),
+    /// Second sink failed
+    Second(B),
+    /// Both sinks failed
+    Both(A, B),
+}
+
+impl<A: core::fmt::Display, B: core::fmt::Display> core::fmt::Display for TeeError<A, B> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            TeeError::First(a) => write!(f, "first sink failed: {}", a),
+            TeeError::Second(b) => write!(f, "second sink failed: {}", b),
+            TeeError::Both(a, b) => write!(f, "both sinks failed: {}; {}", a, b),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<A, B> std::error::Error for TeeError<A, B>
+where
+    A: std::error::Error + 'static,
+    B: std::error::Error + 'static,
+{
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            TeeError::First(a) | TeeError::Both(a, _) => Some(a),
+            TeeError::Second(b) => Some(b),
+        }
+    }
+}
+
+/// Sink which writes same data to two sinks.
+///
+/// By default each chunk is passed to both sinks even if first of them fails, and all
+/// failures are reported (see [`TeeError`]). With [`fail_fast`], second sink does not
+/// receive chunk which first sink failed to process.
+///
+/// ```rust
+/// use formatf::{format_to, CursorSink, Tee, TeeError, Value};
+/// let (mut log, mut buf) = ([0u8; 16], [0u8; 4]);
+/// let mut sink = Tee::new(CursorSink::new(&mut log), CursorSink::new(&mut buf));
+/// let res = format_to(b"%s", &[Value::String(b"hello")], &mut sink);
+/// assert!(matches!(res, Err(formatf::FormatToError::Sink(TeeError::Second(_), 0))));
+/// assert_eq!(sink.get_ref().0.as_bytes(), b"hello");
+/// ```
+///
+/// [`TeeError`]: ./enum.TeeError.html
+/// [`fail_fast`]: #method.fail_fast
+pub struct Tee<A, B> {
+    first: A,
+    second: B,
+    fail_fast: bool,
+}
+
+impl<A: BinSink, B: BinSink> Tee<A, B> {
+    /// Creates sink which writes to `first`, and then to `second`
+    pub fn new(first: A, second: B) -> Self {
+        Tee {
+            first,
+            second,
+            fail_fast: false,
+        }
+    }
+
+    /// If set, chunk is not passed to second sink when first one fails
+    pub fn fail_fast(mut self, fail_fast: bool) -> Self {
+        self.fail_fast = fail_fast;
+        self
+    }
+
+    /// Returns references to underlying sinks
+    pub fn get_ref(&self) -> (&A, &B) {
+        (&self.first, &self.second)
+    }
+
+    /// Returns mutable references to underlying sinks
+    pub fn get_mut(&mut self) -> (&mut A, &mut B) {
+        (&mut self.first, &mut self.second)
+    }
+
+    /// Returns underlying sinks
+    pub fn into_inner(self) -> (A, B) {
+        (self.first, self.second)
+    }
+
+    /// Performs `op` on both sinks, according to error policy
+    fn both(
+        &mut self,
+        op_first: impl FnOnce(&mut A) -> Result<(), A::Err>,
+        op_second: impl FnOnce(&mut B) -> Result<(), B::Err>,
+    ) -> Result<(), TeeError<A::Err, B::Err>> {
+        let first = op_first(&mut self.first);
+        if self.fail_fast && first.is_err() {
+            return first.map_err(TeeError::First);
+        }
+        match (first, op_second(&mut self.second)) {
+            (Ok(()), Ok(())) => Ok(()),
+            (Err(a), Ok(())) => Err(TeeError::First(a)),
+            (Ok(()), Err(b)) => Err(TeeError::Second(b)),
+            (Err(a), Err(b)) => Err(TeeError::Both(a, b)),
+        }
+    }
+}
+
+impl<A: BinSink, B: BinSink> BinSink for Tee<A, B> {
+    type Err = TeeError<A::Err, B::Err>;
+
+    fn put(&mut self, data: &[u8]) -> Result<(), Self::Err> {
+        self.both(|a| a.put(data), |b| b.put(data))
+    }
+
+    fn put_repeat(&mut self, byte: u8, count: usize) -> Result<(), Self::Err> {
+        self.both(|a| a.put_repeat(byte, count), |b| b.put_repeat(byte, count))
+    }
+
+    fn put_vectored(&mut self, chunks: &[&[u8]]) -> Result<(), Self::Err> {
+        self.both(|a| a.put_vectored(chunks), |b| b.put_vectored(chunks))
+    }
+
+    fn is_vectored(&self) -> bool {
+        self.first.is_vectored() || self.second.is_vectored()
+    }
+}
+
+impl<A: Rollback, B: Rollback> Rollback for Tee<A, B> {
+    type Checkpoint = (A::Checkpoint, B::Checkpoint);
+
+    fn checkpoint(&self) -> Self::Checkpoint {
+        (self.first.checkpoint(), self.second.checkpoint())
+    }
+
+    fn rollback(&mut self, checkpoint: Self::Checkpoint) {
+        self.first.rollback(checkpoint.0);
+        self.second.rollback(checkpoint.1);
+    }
+}
+
+/// Sink which writes to `primary` sink until it fails, and to `secondary` after that.
+///
+/// Chunk which `primary` failed to process is passed to `secondary` as a whole, even if
+/// part of it was written. Error of `primary` can be retrieved with [`primary_error`].
+///
+/// ```rust
+/// use formatf::{format_to, CursorSink, Fallback, Value};
+/// let (mut buf, mut spare) = ([0u8; 4], [0u8; 16]);
+/// let mut sink = Fallback::new(CursorSink::new(&mut buf), CursorSink::new(&mut spare));
+/// format_to(b"ab%s|cd", &[Value::String(b"xyz")], &mut sink).unwrap();
+/// assert!(sink.primary_error().is_some());
+/// let (primary, secondary) = sink.into_inner();
+/// assert_eq!(primary.as_bytes(), b"ab");
+/// assert_eq!(secondary.as_bytes(), b"xyz|cd");
+/// ```
+///
+/// [`primary_error`]: #method.primary_error
+pub struct Fallback<A: BinSink, B> {
+    primary: A,
+    secondary: B,
+    primary_error: Option<A::Err>,
+}
+
+impl<A: BinSink, B: BinSink> Fallback<A, B> {
+    /// Creates sink which writes to `primary` until it fails, and then to `secondary`
+    pub fn new(primary: A, secondary: B) -> Self {
+        Fallback {
+            primary,
+            secondary,
+            primary_error: None,
+        }
+    }
+
+    /// Returns error of primary sink, if it failed
+    pub fn primary_error(&self) -> Option<&A::Err> {
+        self.primary_error.as_ref()
+    }
+
+    /// Returns references to underlying sinks
+    pub fn get_ref(&self) -> (&A, &B) {
+        (&self.primary, &self.secondary)
+    }
+
+    /// Returns mutable references to underlying sinks
+    pub fn get_mut(&mut self) -> (&mut A, &mut B) {
+        (&mut self.primary, &mut self.secondary)
+    }
+
+    /// Returns underlying sinks
+    pub fn into_inner(self) -> (A, B) {
+        (self.primary, self.secondary)
+    }
+
+    /// Performs `op` on primary sink, switching to secondary one on failure
+    fn try_primary(
+        &mut self,
+        op_primary: impl FnOnce(&mut A) -> Result<(), A::Err>,
+        op_secondary: impl FnOnce(&mut B) -> Result<(), B::Err>,
+    ) -> Result<(), B::Err> {
+        if self.primary_error.is_none() {
+            match op_primary(&mut self.primary) {
+                Ok(()) => return Ok(()),
+                Err(e) => self.primary_error = Some(e),
+            }
+        }
+        op_secondary(&mut self.secondary)
+    }
+}
+
+impl<A: BinSink, B: BinSink> BinSink for Fallback<A, B> {
+    type Err = B::Err;
+
+    fn put(&mut self, data: &[u8]) -> Result<(), Self::Err> {
+        self.try_primary(|a| a.put(data), |b| b.put(data))
+    }
+
+    fn put_repeat(&mut self, byte: u8, count: usize) -> Result<(), Self::Err> {
+        self.try_primary(|a| a.put_repeat(byte, count), |b| b.put_repeat(byte, count))
+    }
+
+    fn put_vectored(&mut self, chunks: &[&[u8]]) -> Result<(), Self::Err> {
+        self.try_primary(|a| a.put_vectored(chunks), |b| b.put_vectored(chunks))
+    }
+
+    fn is_vectored(&self) -> bool {
+        match self.primary_error {
+            None => self.primary.is_vectored(),
+            Some(_) => self.secondary.is_vectored(),
+        }
+    }
+}
+
+/// Sink which converts errors of underlying sink using function
+///
+/// ```rust
+/// use formatf::{format_to, CursorSink, MapErr, Value};
+/// let mut buf = [0u8; 2];
+/// let mut sink = MapErr::new(CursorSink::new(&mut buf), |_| "buffer is full");
+/// let res = format_to(b"%d", &[Value::Int(100)], &mut sink);
+/// assert!(matches!(res, Err(formatf::FormatToError::Sink("buffer is full", 0))));
+/// ```
+pub struct MapErr<S, F> {
+    sink: S,
+    f: F,
+}
+
+impl<S, F> MapErr<S, F> {
+    /// Creates sink which writes to `sink` and converts its errors with `f`
+    pub fn new(sink: S, f: F) -> Self {
+        MapErr { sink, f }
+    }
+
+    /// Returns reference to underlying sink
+    pub fn get_ref(&self) -> &S {
+        &self.sink
+    }
+
+    /// Returns mutable reference to underlying sink
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.sink
+    }
+
+    /// Returns underlying sink
+    pub fn into_inner(self) -> S {
+        self.sink
+    }
+}
+
+impl<S: BinSink, E, F: FnMut(S::Err) -> E> BinSink for MapErr<S, F> {
+    type Err = E;
+
+    fn put(&mut self, data: &[u8]) -> Result<(), Self::Err> {
+        self.sink.put(data).map_err(&mut self.f)
+    }
+
+    fn put_repeat(&mut self, byte: u8, count: usize) -> Result<(), Self::Err> {
+        self.sink.put_repeat(byte, count).map_err(&mut self.f)
+    }
+
+    fn put_vectored(&mut self, chunks: &[&[u8]]) -> Result<(), Self::Err> {
+        self.sink.put_vectored(chunks).map_err(&mut self.f)
+    }
+
+    fn is_vectored(&self) -> bool {
+        self.sink.is_vectored()
+    }
+}
+
+impl<S: Rollback, E, F: FnMut(S::Err) -> E> Rollback for MapErr<S, F> {
+    type Checkpoint = S::Checkpoint;
+
+    fn checkpoint(&self) -> Self::Checkpoint {
+        self.sink.checkpoint()
+    }
+
+    fn rollback(&mut self, checkpoint: Self::Checkpoint) {
+        self.sink.rollback(checkpoint)
+    }
+}
+
+#[cfg(all(test, feature = "alloc"))]
+mod tests {
+    use super::*;
+    use crate::{format_to, CursorSink, FormatToError, SliceTooSmall, Value};
+
+    #[test]
+    fn tee() {
+        let args = [Value::String(b"abcdef")];
+        let mut buf = [0u8; 4];
+        let mut sink = Tee::new(CursorSink::new(&mut buf), Vec::new());
+        let res = format_to(b"%s", &args, &mut sink);
+        assert!(matches!(
+            res,
+            Err(FormatToError::Sink(TeeError::First(SliceTooSmall), 0))
+        ));
+        assert_eq!(sink.get_ref().1, b"abcdef");
+
+        let mut sink = Tee::new(CursorSink::new(&mut buf), Vec::new()).fail_fast(true);
+        assert!(format_to(b"%s", &args, &mut sink).is_err());
+        assert!(sink.get_ref().1.is_empty());
+
+        let mut small = [0u8; 2];
+        let mut sink = Tee::new(CursorSink::new(&mut buf), CursorSink::new(&mut small));
+        let res = format_to(b"%s", &args, &mut sink);
+        assert!(matches!(
+            res,
+            Err(FormatToError::Sink(TeeError::Both(..), 0))
+        ));
+
+        let mut sink = Tee::new(Vec::new(), Vec::new());
+        assert_eq!(format_to(b"%-5s|", &args[..1], &mut sink).unwrap(), 7);
+        let (a, b) = sink.into_inner();
+        assert_eq!(
+            (a.as_slice(), b.as_slice()),
+            (&b"abcdef|"[..], &b"abcdef|"[..])
+        );
+    }
+
+    #[test]
+    fn fallback() {
+        let mut buf = [0u8; 4];
+        let mut sink = Fallback::new(CursorSink::new(&mut buf), Vec::new());
+        format_to(b"%3d%3d", &[Value::Int(1), Value::Int(2)], &mut sink).unwrap();
+        let (primary, secondary) = sink.into_inner();
+        assert_eq!(primary.as_bytes(), b"  1");
+        assert_eq!(secondary, b"  2");
+
+        let mut small = [0u8; 1];
+        let mut sink = Fallback::new(CursorSink::new(&mut buf), CursorSink::new(&mut small));
+        let res = format_to(b"%s", &[Value::String(b"abcdef")], &mut sink);
+        assert!(matches!(res, Err(FormatToError::Sink(SliceTooSmall, 0))));
+        assert!(sink.primary_error().is_some());
+        assert_eq!(sink.get_ref().0.as_bytes(), b"");
+    }
+
+    #[test]
+    fn map_err() {
+        let mut buf = [0u8; 4];
+        let mut sink = MapErr::new(CursorSink::new(&mut buf), |SliceTooSmall| "full");
+        assert_eq!(format_to(b"%d|", &[Value::Int(12)], &mut sink).unwrap(), 3);
+        let checkpoint = sink.checkpoint();
+        let res = format_to(b"%d", &[Value::Int(34)], &mut sink);
+        assert!(matches!(res, Err(FormatToError::Sink("full", 0))));
+        sink.get_mut().put(b"!").unwrap();
+        assert_eq!(sink.get_ref().as_bytes(), b"12|!");
+        sink.rollback(checkpoint);
+        assert_eq!(sink.into_inner().as_bytes(), b"12|");
+    }
+}
@@ -0,0 +1,283 @@
+//! Human-readable rendering of formatting errors
+//!
+//! Output resembles compiler diagnostics:
+//! ```text
+//! error: argument type not compatible with conversion specifier
+//!   |
+//!   | Hello, %ls!
+//!   |        ^^^ `%ls` expects a wide string but argument 1 is Int
+//! ```
+//! Arguments are numbered starting from 1.
+//! Template bytes that are not valid UTF-8, as well as control characters, are escaped, so
+//! rendered text is always safe to print.
+
+use crate::{
+    high::{ConvKind, LenModifier, ParseError, ParsedConversionSpecification},
+    visit::{self, ConversionSpecification, FormatStringVisitor},
+    ErrorLocation, FormatToError, Value,
+};
+use core::fmt::Write;
+
+/// Renders `error` which occured while formatting `template` with `args`.
+///
+/// Returned string is multi-line and ends with newline.
+pub fn render<E: core::fmt::Display>(
+    template: &[u8],
+    args: &[Value],
+    error: &FormatToError<E>,
+    location: &ErrorLocation,
+) -> String {
+    let mut out = String::new();
+    out.push_str("error: ");
+    out.push_str(error.description());
+    if let FormatToError::Sink(inner, _) = error {
+        let _ = write!(out, ": {}", inner);
+    }
+    out.push('\n');
+    let span = location
+        .span
+        .clone()
+        .filter(|span| span.start <= span.end && span.end <= template.len());
+    if let Some(span) = span {
+        let label = label(&template[span.clone()], args, error, location);
+        render_snippet(&mut out, template, span, &label);
+    } else if let (FormatToError::ExcessArgs, Some(used)) = (error, location.arg) {
+        let _ = writeln!(
+            out,
+            "  = note: only {} of {} arguments were used",
+            used,
+            args.len()
+        );
+    }
+    out
+}
+
+/// Escapes `data`, returning escaped text and column for each byte of `data`
+/// (plus one for position after last byte)
+fn escape(data: &[u8]) -> (String, Vec<usize>) {
+    let mut text = String::new();
+    let mut columns = Vec::with_capacity(data.len() + 1);
+    let mut width = 0;
+    let mut rest = data;
+    while !rest.is_empty() {
+        let (valid, invalid_len) = match core::str::from_utf8(rest) {
+            Ok(s) => (s, 0),
+            Err(e) => {
+                let valid = core::str::from_utf8(&rest[..e.valid_up_to()]).unwrap_or_default();
+                (valid, e.error_len().unwrap_or(rest.len() - e.valid_up_to()))
+            }
+        };
+        for ch in valid.chars() {
+            for _ in 0..ch.len_utf8() {
+                columns.push(width);
+            }
+            if ch.is_control() {
+                for esc in ch.escape_default() {
+                    text.push(esc);
+                    width += 1;
+                }
+            } else {
+                text.push(ch);
+                width += 1;
+            }
+        }
+        let invalid = &rest[valid.len()..valid.len() + invalid_len];
+        for &byte in invalid {
+            columns.push(width);
+            let _ = write!(text, "\\x{:02x}", byte);
+            width += 4;
+        }
+        rest = &rest[valid.len() + invalid_len..];
+    }
+    columns.push(width);
+    (text, columns)
+}
+
+fn render_snippet(out: &mut String, template: &[u8], span: core::ops::Range<usize>, label: &str) {
+    let line_start = template[..span.start]
+        .iter()
+        .rposition(|&c| c == b'\n')
+        .map_or(0, |pos| pos + 1);
+    let line_end = template[span.start..]
+        .iter()
+        .position(|&c| c == b'\n')
+        .map_or(template.len(), |pos| span.start + pos);
+    let (line, columns) = escape(&template[line_start..line_end]);
+    let start_col = columns[span.start - line_start];
+    let end_col = columns[core::cmp::min(span.end, line_end) - line_start];
+
+    out.push_str("  |\n");
+    let _ = writeln!(out, "  | {}", line);
+    out.push_str("  | ");
+    for _ in 0..start_col {
+        out.push(' ');
+    }
+    for _ in 0..core::cmp::max(end_col - start_col, 1) {
+        out.push('^');
+    }
+    if !label.is_empty() {
+        out.push(' ');
+        out.push_str(label);
+    }
+    out.push('\n');
+}
+
+/// Visitor which remembers first conversion specification
+#[derive(Default)]
+struct FirstSpec(Option<Result<ParsedConversionSpecification, ParseError>>);
+
+impl FormatStringVisitor for FirstSpec {
+    fn visit_specification(&mut self, spec: ConversionSpecification) {
+        if self.0.is_none() {
+            self.0 = Some(ParsedConversionSpecification::try_parse(spec));
+        }
+    }
+}
+
+/// Describes which argument `spec_text` expects
+fn expected_arg(spec_text: &[u8]) -> Option<&'static str> {
+    let mut first = FirstSpec::default();
+    visit::visit(spec_text, &mut first);
+    let spec = first.0?.ok()?;
+    Some(match (spec.conv_kind, spec.len_modifier) {
+        (ConvKind::SignDecInt, _) => "an integer",
+        (ConvKind::UnsignedDecInt, _) => "an unsigned integer",
+        (ConvKind::String, LenModifier::Long) => "a wide string",
+        (ConvKind::String, _) => "a string",
+    })
+}
+
+fn spec_error_label(err: &ParseError) -> String {
+    match err {
+        ParseError::UnknownFlag(c) => format!("unknown flag `{}`", escape(&[*c]).0),
+        ParseError::DuplicateFlag(c) => format!("flag `{}` is repeated", escape(&[*c]).0),
+        ParseError::IncompatibleFlag(c) => {
+            format!(
+                "flag `{}` is not allowed with this conversion",
+                escape(&[*c]).0
+            )
+        }
+        ParseError::InvalidPrec(Some(e)) => format!("invalid precision: {}", e),
+        ParseError::InvalidWidth(Some(e)) => format!("invalid width: {}", e),
+        other => other.description().to_string(),
+    }
+}
+
+fn label<E>(
+    spec_text: &[u8],
+    args: &[Value],
+    error: &FormatToError<E>,
+    location: &ErrorLocation,
+) -> String {
+    let spec = escape(spec_text).0;
+    let arg = location
+        .arg
+        .and_then(|i| args.get(i).map(|arg| (i + 1, arg)));
+    match (error, arg) {
+        (FormatToError::BadType, Some((num, arg))) => match expected_arg(spec_text) {
+            Some(expected) => format!(
+                "`{}` expects {} but argument {} is {}",
+                spec,
+                expected,
+                num,
+                arg.kind_name()
+            ),
+            None => format!("argument {} is {}", num, arg.kind_name()),
+        },
+        (FormatToError::NumOverflow, Some((num, Value::Int(x)))) => {
+            format!("argument {} ({}) does not fit into `{}`", num, x, spec)
+        }
+        (FormatToError::Coercion(e), Some((num, Value::String(s)))) => {
+            let mut label = format!("argument {} (\"{}\") is not a number", num, escape(s).0);
+            if let Some(e) = e {
+                let _ = write!(label, ": {}", e);
+            }
+            label
+        }
+        (FormatToError::NotEnoughArguments, _) => format!(
+            "`{}` requires argument {} but {} given",
+            spec,
+            location.arg.map_or(args.len(), |i| i) + 1,
+            match args.len() {
+                0 => "none were".to_string(),
+                1 => "only 1 was".to_string(),
+                n => format!("only {} were", n),
+            }
+        ),
+        (FormatToError::Spec(e), _) => spec_error_label(e),
+        (FormatToError::Forbidden, _) => format!("`{}` is not allowed here", spec),
+        (FormatToError::LimitExceeded, _) => format!("`{}` exceeds width limit", spec),
+        (FormatToError::OutputTooLarge, _) => "output exceeds size limit here".to_string(),
+        (FormatToError::TooManySpecs, _) => "specification limit is exceeded here".to_string(),
+        (FormatToError::Unsupported, _) => format!("`{}` uses unsupported feature", spec),
+        (FormatToError::Invalid, _) => {
+            format!("`{}` combines flags and conversion in invalid way", spec)
+        }
+        _ => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{format, Value};
+
+    fn check(template: &[u8], args: &[Value], expected: &str) {
+        let err = format(template, args).unwrap_err();
+        assert_eq!(err.render(template, args), expected);
+    }
+
+    #[test]
+    fn bad_type() {
+        check(
+            b"Hello, %s and %ls!",
+            &[Value::String(b"x"), Value::Int(5)],
+            "error: argument type not compatible with conversion specifier
+  |
+  | Hello, %s and %ls!
+  |               ^^^ `%ls` expects a wide string but argument 2 is Int
+",
+        );
+    }
+
+    #[test]
+    fn not_enough_args() {
+        check(
+            b"first line\n%d %d\nlast line",
+            &[Value::Int(1)],
+            "error: format string requested arguments that were not provided
+  |
+  | %d %d
+  |    ^^ `%d` requires argument 2 but only 1 was given
+",
+        );
+    }
+
+    #[test]
+    fn excess_args() {
+        let options = crate::FormatOptions {
+            excess_args: crate::ExcessArgsPolicy::Error,
+            ..Default::default()
+        };
+        let args = [Value::Int(1), Value::Int(2)];
+        let err = crate::format_with(b"%d", &args, &options).unwrap_err();
+        assert_eq!(
+            err.render(b"%d", &args),
+            "error: format string did not use all given args
+  = note: only 1 of 2 arguments were used
+"
+        );
+    }
+
+    #[test]
+    fn escapes_bytes() {
+        check(
+            b"\xff\t\xd0\xb0 %hhd",
+            &[Value::Int(300)],
+            "error: numeric overflow
+  |
+  | \\xff\\t\u{430} %hhd
+  |         ^^^^ argument 1 (300) does not fit into `%hhd`
+",
+        );
+    }
+}
@@ -0,0 +1,219 @@
+//! Output to raw file descriptors, usable where allocation and locks are forbidden
+
+use crate::BinSink;
+
+/// Error returned by [`FdSink`]. Contains `errno` value
+///
+/// [`FdSink`]: ./struct.FdSink.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Errno(pub i32);
+
+impl core::fmt::Display for Errno {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "write failed with errno {}", self.0)
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for Errno {}
+
+/// Maximum number of chunks passed to single `writev` call
+const MAX_IOVECS: usize = 16;
+
+/// Helper struct that implements BinSink via `write(2)` and `writev(2)` syscalls.
+///
+/// This Sink is async-signal-safe: it does not allocate, take locks or use thread-local
+/// state other than `errno`, so it can be used in signal handlers and in child process
+/// after `fork()`. `format_to` itself does not allocate either, so formatting to this sink
+/// is async-signal-safe as a whole, as long as `FormatOptions` do not contain callbacks
+/// which are not.
+///
+/// Interrupted (`EINTR`) and short writes are retried. Output of one `format_to` call is
+/// usually passed to single `writev` call, see `BinSink::is_vectored`.
+///
+/// File descriptor is not closed on drop.
+///
+/// ```rust
+/// use formatf::{format_to, FdSink, Value};
+/// let mut stderr = FdSink::new(2);
+/// format_to(b"caught signal %d\n", &[Value::Int(11)], &mut stderr).unwrap();
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FdSink {
+    fd: libc::c_int,
+}
+
+impl FdSink {
+    /// Creates sink writing to `fd`, which must stay open while sink is used
+    pub fn new(fd: libc::c_int) -> Self {
+        FdSink { fd }
+    }
+
+    /// Returns underlying file descriptor
+    pub fn fd(&self) -> libc::c_int {
+        self.fd
+    }
+}
+
+fn errno() -> Errno {
+    // SAFETY: `__errno_location` always returns valid pointer to thread's `errno`
+    Errno(unsafe { *libc::__errno_location() })
+}
+
+/// Calls `write` until whole `data` is written, retrying on `EINTR`.
+///
+/// `write` returns number of written bytes or negative value on error, like `write(2)`.
+/// Writing nothing is reported as `EIO`, since retrying would loop forever.
+fn write_all(mut data: &[u8], mut write: impl FnMut(&[u8]) -> isize) -> Result<(), Errno> {
+    while !data.is_empty() {
+        let res = write(data);
+        if res < 0 {
+            let err = errno();
+            if err.0 == libc::EINTR {
+                continue;
+            }
+            return Err(err);
+        }
+        if res == 0 {
+            return Err(Errno(libc::EIO));
+        }
+        data = &data[res as usize..];
+    }
+    Ok(())
+}
+
+/// Same as [`write_all`], but for `writev(2)`. Written part of `iovs` is consumed
+fn writev_all(
+    mut iovs: &mut [libc::iovec],
+    mut writev: impl FnMut(&[libc::iovec]) -> isize,
+) -> Result<(), Errno> {
+    loop {
+        while iovs.first().is_some_and(|iov| iov.iov_len == 0) {
+            iovs = &mut iovs[1..];
+        }
+        if iovs.is_empty() {
+            return Ok(());
+        }
+        let res = writev(iovs);
+        if res < 0 {
+            let err = errno();
+            if err.0 == libc::EINTR {
+                continue;
+            }
+            return Err(err);
+        }
+        if res == 0 {
+            return Err(Errno(libc::EIO));
+        }
+        // skip written part
+        let mut written = res as usize;
+        for iov in iovs.iter_mut() {
+            let len = core::cmp::min(written, iov.iov_len);
+            // SAFETY: `len` does not exceed length of buffer
+            iov.iov_base = unsafe { iov.iov_base.cast::<u8>().add(len).cast() };
+            iov.iov_len -= len;
+            written -= len;
+            if written == 0 {
+                break;
+            }
+        }
+    }
+}
+
+impl BinSink for FdSink {
+    type Err = Errno;
+
+    fn put(&mut self, data: &[u8]) -> Result<(), Self::Err> {
+        let fd = self.fd;
+        // SAFETY: pointer and length describe valid slice
+        write_all(data, |data| unsafe {
+            libc::write(fd, data.as_ptr().cast(), data.len())
+        })
+    }
+
+    fn put_vectored(&mut self, chunks: &[&[u8]]) -> Result<(), Self::Err> {
+        let fd = self.fd;
+        for group in chunks.chunks(MAX_IOVECS) {
+            let mut storage = [libc::iovec {
+                iov_base: core::ptr::null_mut(),
+                iov_len: 0,
+            }; MAX_IOVECS];
+            for (iov, chunk) in storage.iter_mut().zip(group) {
+                *iov = libc::iovec {
+                    iov_base: chunk.as_ptr() as *mut libc::c_void,
+                    iov_len: chunk.len(),
+                };
+            }
+            // SAFETY: each iovec describes valid slice, and `writev` does not write to them
+            writev_all(&mut storage[..group.len()], |iovs| unsafe {
+                libc::writev(fd, iovs.as_ptr(), iovs.len() as libc::c_int)
+            })?;
+        }
+        Ok(())
+    }
+
+    fn is_vectored(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{format_to, Value};
+
+    #[test]
+    fn pipe() {
+        let mut fds = [0; 2];
+        // SAFETY: `fds` has space for two descriptors
+        assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+        let mut sink = FdSink::new(fds[1]);
+        let args = [
+            Value::String(b"SIGSEGV"),
+            Value::Int(11),
+            Value::Int(-1),
+            Value::String(b"x"),
+        ];
+        let written = format_to(b"%s (%d) at %5d;%-70s|\n", &args, &mut sink);
+        assert_eq!(written.unwrap(), 94);
+
+        let mut buf = [0u8; 256];
+        // SAFETY: `buf` is valid for writes of its length
+        let read = unsafe { libc::read(fds[0], buf.as_mut_ptr().cast(), buf.len()) };
+        let expected = [&b"SIGSEGV (11) at    -1;x"[..], &[b' '; 69], b"|\n"].concat();
+        assert_eq!(&buf[..read as usize], &expected[..]);
+
+        // SAFETY: closing descriptors created above
+        unsafe {
+            libc::close(fds[0]);
+            libc::close(fds[1]);
+        }
+    }
+
+    #[test]
+    fn zero_write() {
+        // `write` returning 0 used to be retried forever
+        assert_eq!(write_all(b"xy", |_| 0), Err(Errno(libc::EIO)));
+        let mut calls = 0;
+        let res = write_all(b"xy", |_| {
+            calls += 1;
+            2 - calls
+        });
+        assert_eq!((res, calls), (Err(Errno(libc::EIO)), 2));
+
+        let data = b"xy";
+        let mut iovs = [libc::iovec {
+            iov_base: data.as_ptr() as *mut libc::c_void,
+            iov_len: data.len(),
+        }];
+        assert_eq!(writev_all(&mut iovs, |_| 0), Err(Errno(libc::EIO)));
+        assert_eq!(writev_all(&mut iovs, |_| 2), Ok(()));
+    }
+
+    #[test]
+    fn bad_fd() {
+        let mut sink = FdSink::new(-1);
+        assert_eq!(sink.put(b"x"), Err(Errno(libc::EBADF)));
+        assert_eq!(sink.put_vectored(&[b"x", b"y"]), Err(Errno(libc::EBADF)));
+    }
+}
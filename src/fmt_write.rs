@@ -0,0 +1,149 @@
+//! Integration with `core::fmt`
+
+use crate::{format_to_with, utf8::Utf8Decoder, BinSink, FormatOptions, Value};
+use core::fmt::{self, Write};
+
+/// Describes how [`FmtWriteSink`] handles data which is not valid UTF-8
+///
+/// [`FmtWriteSink`]: ./struct.FmtWriteSink.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NonUtf8 {
+    /// Each invalid sequence is replaced with `U+FFFD`, like `String::from_utf8_lossy`
+    /// does. This is default.
+    #[default]
+    Replace,
+    /// Each invalid byte is written as `\xNN`
+    Escape,
+    /// Sink fails
+    Error,
+}
+
+/// Helper struct that implements BinSink via delegation to `core::fmt::Write`.
+///
+/// Character may be split between several chunks, so [`finish`] should be called after
+/// formatting to handle incomplete character at the end of output.
+///
+/// ```rust
+/// use formatf::{format_to, FmtWriteSink, NonUtf8, Value};
+/// let mut s = String::new();
+/// let mut sink = FmtWriteSink::new(&mut s).non_utf8(NonUtf8::Escape);
+/// format_to(b"%s!", &[Value::String(b"a\xffb")], &mut sink).unwrap();
+/// sink.finish().unwrap();
+/// assert_eq!(s, "a\\xffb!");
+/// ```
+///
+/// [`finish`]: #method.finish
+pub struct FmtWriteSink<W> {
+    writer: W,
+    non_utf8: NonUtf8,
+    decoder: Utf8Decoder,
+}
+
+impl<W: Write> FmtWriteSink<W> {
+    /// Creates sink writing to `writer`, replacing invalid UTF-8 with `U+FFFD`
+    pub fn new(writer: W) -> Self {
+        FmtWriteSink {
+            writer,
+            non_utf8: NonUtf8::default(),
+            decoder: Utf8Decoder::default(),
+        }
+    }
+
+    /// Sets how invalid UTF-8 is handled
+    pub fn non_utf8(mut self, non_utf8: NonUtf8) -> Self {
+        self.non_utf8 = non_utf8;
+        self
+    }
+
+    /// Handles incomplete character at the end of output, as configured by `non_utf8`
+    pub fn finish(&mut self) -> fmt::Result {
+        let (writer, non_utf8) = (&mut self.writer, self.non_utf8);
+        self.decoder
+            .finish(&mut |piece| write_piece(writer, non_utf8, piece))
+    }
+
+    /// Returns underlying writer
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+fn write_piece<W: Write>(
+    writer: &mut W,
+    non_utf8: NonUtf8,
+    piece: Result<&str, &[u8]>,
+) -> fmt::Result {
+    match (piece, non_utf8) {
+        (Ok(s), _) => writer.write_str(s),
+        (Err(_), NonUtf8::Replace) => writer.write_char(char::REPLACEMENT_CHARACTER),
+        (Err(invalid), NonUtf8::Escape) => invalid
+            .iter()
+            .try_for_each(|byte| write!(writer, "\\x{:02x}", byte)),
+        (Err(_), NonUtf8::Error) => Err(fmt::Error),
+    }
+}
+
+impl<W: Write> BinSink for FmtWriteSink<W> {
+    type Err = fmt::Error;
+
+    fn put(&mut self, data: &[u8]) -> Result<(), Self::Err> {
+        let (writer, non_utf8) = (&mut self.writer, self.non_utf8);
+        self.decoder
+            .decode(data, &mut |piece| write_piece(writer, non_utf8, piece))
+    }
+}
+
+/// Template with arguments, which is formatted when displayed.
+///
+/// By default errors are rendered inline (see `FormatOptions::inline_errors`), so
+/// displaying never fails. Invalid UTF-8 is replaced with `U+FFFD`.
+///
+/// ```rust
+/// use formatf::{Formatted, Value};
+/// let args = [Value::String(b"world"), Value::Int(42)];
+/// let msg = Formatted::new(b"Hello, %s! %5d", &args);
+/// assert_eq!(format!("[{}]", msg), "[Hello, world!    42]");
+/// let msg = Formatted::new(b"%d", &args);
+/// assert_eq!(msg.to_string(), "%!d(string=world)%!(EXTRA int=42)");
+/// ```
+#[derive(Clone, Copy)]
+pub struct Formatted<'a> {
+    template: &'a [u8],
+    args: &'a [Value<'a>],
+    options: Option<&'a FormatOptions<'a>>,
+}
+
+impl<'a> Formatted<'a> {
+    /// Creates value formatting `template` with `args` using default options
+    pub fn new(template: &'a [u8], args: &'a [Value<'a>]) -> Self {
+        Formatted {
+            template,
+            args,
+            options: None,
+        }
+    }
+
+    /// Uses given `options` instead of default ones.
+    ///
+    /// Note that if `options` allow formatting errors, displaying fails on error, and
+    /// methods like `to_string` panic.
+    pub fn options(mut self, options: &'a FormatOptions<'a>) -> Self {
+        self.options = Some(options);
+        self
+    }
+}
+
+impl fmt::Display for Formatted<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut sink = FmtWriteSink::new(&mut *f);
+        let res = match self.options {
+            Some(options) => format_to_with(self.template, self.args, &mut sink, options),
+            None => {
+                let options = FormatOptions::new().inline_errors(true);
+                format_to_with(self.template, self.args, &mut sink, &options)
+            }
+        };
+        res.map_err(|_| fmt::Error)?;
+        sink.finish()
+    }
+}
@@ -1,13 +1,19 @@
 use crate::{
     high::{ConvKind, LenModifier, ParseError, ParsedConversionSpecification},
-    visit::{ConversionSpecification, FormatStringVisitor},
-    BinSink, Value,
+    limits,
+    options::{ExcessArgsPolicy, Locale, OverflowPolicy},
+    visit::{self, ConversionSpecification, FormatStringVisitor},
+    BinSink, CountSink, FormatOptions, Value,
 };
 
 #[derive(Debug)]
 pub enum FormatToError<E> {
     /// `BinSink` returned error.
-    Sink(E),
+    ///
+    /// Second field is number of bytes which were successfully written before error.
+    /// For vectored sinks (see `BinSink::is_vectored`) it does not include bytes from
+    /// the failed `put_vectored` call, even if some of them were written.
+    Sink(E, usize),
     /// Conversion specifier parse error.
     Spec(ParseError),
     /// Too many arguments.
@@ -26,21 +32,64 @@ pub enum FormatToError<E> {
     Invalid,
     /// Value passed was out of numeric limits for conversion requested.
     NumOverflow,
+    /// String argument could not be converted to number.
+    ///
+    /// Only returned if `FormatOptions::coerce_args` is enabled.
+    Coercion(Option<core::num::ParseIntError>),
+    /// Format string uses conversion or flag which is not allowed by `FormatOptions`.
+    Forbidden,
+    /// Field width or precision is larger than allowed by `FormatOptions`.
+    LimitExceeded,
+    /// Output would be larger than allowed by `FormatOptions`.
+    OutputTooLarge,
+    /// Format string contains more conversion specifications than allowed by `FormatOptions`.
+    TooManySpecs,
 }
 
 impl<E> FormatToError<E> {
+    /// Converts sink error using `f`, leaving other variants as is
+    pub fn map_sink<F>(self, f: impl FnOnce(E) -> F) -> FormatToError<F> {
+        match self.split_sink() {
+            Ok(err) => err,
+            Err((err, written)) => FormatToError::Sink(f(err), written),
+        }
+    }
+
+    /// Separates sink error and number of written bytes from other errors, which do not
+    /// depend on sink type
+    pub(crate) fn split_sink<F>(self) -> Result<FormatToError<F>, (E, usize)> {
+        Ok(match self {
+            Self::Sink(err, written) => return Err((err, written)),
+            Self::Spec(err) => FormatToError::Spec(err),
+            Self::ExcessArgs => FormatToError::ExcessArgs,
+            Self::NotEnoughArguments => FormatToError::NotEnoughArguments,
+            Self::Unsupported => FormatToError::Unsupported,
+            Self::BadType => FormatToError::BadType,
+            Self::Invalid => FormatToError::Invalid,
+            Self::NumOverflow => FormatToError::NumOverflow,
+            Self::Coercion(err) => FormatToError::Coercion(err),
+            Self::Forbidden => FormatToError::Forbidden,
+            Self::LimitExceeded => FormatToError::LimitExceeded,
+            Self::OutputTooLarge => FormatToError::OutputTooLarge,
+            Self::TooManySpecs => FormatToError::TooManySpecs,
+        })
+    }
+
     pub fn description(&self) -> &'static str {
         match self {
-            Self::Sink(_) => "sink error",
+            Self::Sink(..) => "sink error",
             Self::Spec(_) => "invalid conversion specifier",
             Self::ExcessArgs => "format string did not use all given args",
-            Self::NotEnoughArguments => {
-                "format string requested arguments that were not provided"
-            }
+            Self::NotEnoughArguments => "format string requested arguments that were not provided",
             Self::Unsupported => "this feature is not implemented yet",
             Self::BadType => "argument type not compatible with conversion specifier",
             Self::Invalid => "invalid format string",
             Self::NumOverflow => "numeric overflow",
+            Self::Coercion(_) => "string argument is not a valid number",
+            Self::Forbidden => "conversion or flag is not allowed",
+            Self::LimitExceeded => "field width or precision is too large",
+            Self::OutputTooLarge => "output is too large",
+            Self::TooManySpecs => "too many conversion specifications",
         }
     }
 }
@@ -55,32 +104,742 @@ impl<E: core::fmt::Display> core::fmt::Display for FormatToError<E> {
 impl<E: std::error::Error + 'static> std::error::Error for FormatToError<E> {
     fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
         match self {
-            Self::Sink(inner) => Some(inner),
+            Self::Sink(inner, _) => Some(inner),
             Self::Spec(inner) => Some(inner),
+            Self::Coercion(inner) => inner
+                .as_ref()
+                .map(|x| x as &(dyn std::error::Error + 'static)),
             _ => None,
         }
     }
 }
 
+/// Describes where in format string and argument list an error occured.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ErrorLocation {
+    /// Position of conversion specification being processed (including leading `%`).
+    ///
+    /// `None` if error is not related to a particular specification
+    pub span: Option<core::ops::Range<usize>>,
+    /// Zero-based index of argument being processed.
+    ///
+    /// For `NotEnoughArguments` this is index of missing argument
+    pub arg: Option<usize>,
+}
+
+/// Returns part of `b` before first NUL byte
+pub(crate) fn until_nul(b: &[u8]) -> &[u8] {
+    match b.iter().position(|&c| c == 0) {
+        Some(pos) => &b[..pos],
+        None => b,
+    }
+}
+
+/// Integer converted to C type
+enum Int {
+    Signed(i128),
+    Unsigned(u128),
+}
+
+/// Converts `x` to C integer type with given width and signedness.
+///
+/// Returns `None` if `x` does not fit and `policy` is `OverflowPolicy::Error`.
+fn convert_int(x: i128, bits: u32, signed: bool, policy: OverflowPolicy) -> Option<Int> {
+    if signed {
+        let max = (u128::MAX >> (129 - bits)) as i128;
+        let min = -max - 1;
+        if min <= x && x <= max {
+            return Some(Int::Signed(x));
+        }
+        match policy {
+            OverflowPolicy::Error => None,
+            OverflowPolicy::Saturate => Some(Int::Signed(x.clamp(min, max))),
+            OverflowPolicy::Wrap => {
+                // truncate to `bits` and sign-extend back
+                let shift = 128 - bits;
+                Some(Int::Signed((x << shift) >> shift))
+            }
+        }
+    } else {
+        let max = u128::MAX >> (128 - bits);
+        if 0 <= x && x as u128 <= max {
+            return Some(Int::Unsigned(x as u128));
+        }
+        match policy {
+            OverflowPolicy::Error => None,
+            OverflowPolicy::Saturate if x < 0 => Some(Int::Unsigned(0)),
+            OverflowPolicy::Saturate => Some(Int::Unsigned(max)),
+            OverflowPolicy::Wrap => Some(Int::Unsigned(x as u128 & max)),
+        }
+    }
+}
+
+/// Parses string argument as number, like `printf(1)` does.
+///
+/// Accepts decimal, hexadecimal (`0x1F`) and octal (`017`) numbers with optional sign,
+/// and character codes (`'A`). Leading whitespace is ignored, and empty string means zero.
+fn coerce_to_int(s: &[u8]) -> Result<i128, Option<core::num::ParseIntError>> {
+    let start = s
+        .iter()
+        .position(|c| !c.is_ascii_whitespace())
+        .unwrap_or(s.len());
+    let s = &s[start..];
+    let (negative, digits) = match s {
+        [] => return Ok(0),
+        [b'\'', rest @ ..] | [b'"', rest @ ..] => return Ok(char_code(rest)),
+        [b'-', rest @ ..] => (true, rest),
+        [b'+', rest @ ..] => (false, rest),
+        _ => (false, s),
+    };
+    let (radix, digits) = match digits {
+        [b'0', b'x', rest @ ..] | [b'0', b'X', rest @ ..] => (16, rest),
+        [b'0', rest @ ..] if !rest.is_empty() => (8, rest),
+        _ => (10, digits),
+    };
+    // `from_str_radix` accepts sign, which must not appear here
+    if let Some(b'+') | Some(b'-') = digits.first() {
+        return Err(None);
+    }
+    let digits = core::str::from_utf8(digits).map_err(|_| None)?;
+    let value = i128::from_str_radix(digits, radix).map_err(Some)?;
+    Ok(if negative { -value } else { value })
+}
+
+/// Returns code of first character of `s`, or zero if `s` is empty.
+///
+/// If `s` does not start with valid UTF-8 character, first byte is used.
+fn char_code(s: &[u8]) -> i128 {
+    let prefix = &s[..core::cmp::min(s.len(), 4)];
+    let valid = match core::str::from_utf8(prefix) {
+        Ok(valid) => valid,
+        Err(e) => core::str::from_utf8(&prefix[..e.valid_up_to()]).unwrap_or_default(),
+    };
+    match (valid.chars().next(), s.first()) {
+        (Some(ch), _) => ch as i128,
+        (None, Some(&byte)) => byte as i128,
+        (None, None) => 0,
+    }
+}
+
+/// Enough to hold sign, 39 digits of `u128` and 38 four-byte separators
+const GROUPED_INT_LEN: usize = 1 + 39 + 38 * 4;
+
+/// Inserts thousands separators into decimal number `digits`, writing result to `out`.
+///
+/// Returns length of result
+fn group_digits(digits: &[u8], locale: Locale, out: &mut [u8; GROUPED_INT_LEN]) -> usize {
+    let (sign, digits) = match digits {
+        [b'-', rest @ ..] => (&b"-"[..], rest),
+        _ => (&b""[..], digits),
+    };
+    let mut sep_buf = [0; 4];
+    let sep = match locale.thousands_sep {
+        Some(sep) if locale.group_size != 0 => sep.encode_utf8(&mut sep_buf).as_bytes(),
+        _ => &[],
+    };
+    let group_size = usize::from(locale.group_size);
+    let mut len = 0;
+    let mut push = |data: &[u8]| {
+        out[len..len + data.len()].copy_from_slice(data);
+        len += data.len();
+    };
+    push(sign);
+    for (i, digit) in digits.iter().enumerate() {
+        if i != 0 && (digits.len() - i) % group_size.max(1) == 0 {
+            push(sep);
+        }
+        push(core::slice::from_ref(digit));
+    }
+    len
+}
+
+/// Callback which receives errors in recovery mode
+pub(crate) type ErrorHandler<'a, E> = &'a mut dyn FnMut(FormatToError<E>, ErrorLocation);
+
+/// Callback which is notified when argument is consumed by specification
+pub(crate) type ArgHandler<'a> = &'a mut dyn FnMut(usize, core::ops::Range<usize>);
+
+/// Optional callbacks for formatter
+pub(crate) struct Hooks<'a, E> {
+    /// If set, errors other than `Sink` are passed here, and formatting continues
+    pub(crate) on_error: Option<ErrorHandler<'a, E>>,
+    pub(crate) on_arg: Option<ArgHandler<'a>>,
+}
+
+impl<E> Default for Hooks<'_, E> {
+    fn default() -> Self {
+        Hooks {
+            on_error: None,
+            on_arg: None,
+        }
+    }
+}
+
+/// Maximum number of chunks passed to `BinSink::put_vectored` at once
+const MAX_CHUNKS: usize = 16;
+
+/// Size of buffer for temporary data (e.g. rendered numbers) gathered for vectored sink
+const SCRATCH_LEN: usize = 256;
+
+const SPACES: [u8; 64] = [b' '; 64];
+const ZEROS: [u8; 64] = [b'0'; 64];
+
+/// Piece of output
+#[derive(Clone, Copy)]
+enum Output<'a, 'b> {
+    /// Part of format string or argument, which lives as long as formatter
+    Borrowed(&'a [u8]),
+    /// Data rendered into temporary buffer
+    Temporary(&'b [u8]),
+}
+
+impl Output<'_, '_> {
+    fn bytes(&self) -> &[u8] {
+        match *self {
+            Output::Borrowed(b) => b,
+            Output::Temporary(b) => b,
+        }
+    }
+
+    fn truncate(self, len: usize) -> Self {
+        match self {
+            Output::Borrowed(b) => Output::Borrowed(&b[..len]),
+            Output::Temporary(b) => Output::Temporary(&b[..len]),
+        }
+    }
+
+    fn advance(self, cnt: usize) -> Self {
+        match self {
+            Output::Borrowed(b) => Output::Borrowed(&b[cnt..]),
+            Output::Temporary(b) => Output::Temporary(&b[cnt..]),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Chunk<'a> {
+    Borrowed(&'a [u8]),
+    /// Range in scratch buffer
+    Scratch(usize, usize),
+}
+
+/// Output which is not passed to vectored sink yet
+struct Gathered<'a> {
+    chunks: [Chunk<'a>; MAX_CHUNKS],
+    len: usize,
+    scratch: [u8; SCRATCH_LEN],
+    scratch_len: usize,
+    /// Total size of chunks
+    bytes: usize,
+}
+
+impl<'a> Gathered<'a> {
+    fn new() -> Self {
+        Gathered {
+            chunks: [Chunk::Borrowed(&[]); MAX_CHUNKS],
+            len: 0,
+            scratch: [0; SCRATCH_LEN],
+            scratch_len: 0,
+            bytes: 0,
+        }
+    }
+
+    /// Adds chunk, returning `false` if there is no space for it
+    #[must_use]
+    fn push(&mut self, data: Output<'a, '_>) -> bool {
+        let chunk = match data {
+            Output::Borrowed(b) => Chunk::Borrowed(b),
+            Output::Temporary(b) => {
+                let start = self.scratch_len;
+                if b.len() > SCRATCH_LEN - start {
+                    return false;
+                }
+                self.scratch[start..start + b.len()].copy_from_slice(b);
+                let end = start + b.len();
+                self.scratch_len = end;
+                match self.len.checked_sub(1).map(|last| &mut self.chunks[last]) {
+                    // continue previous chunk
+                    Some(Chunk::Scratch(_, prev_end)) if *prev_end == start => {
+                        *prev_end = end;
+                        self.bytes += b.len();
+                        return true;
+                    }
+                    _ => Chunk::Scratch(start, end),
+                }
+            }
+        };
+        if self.len == MAX_CHUNKS {
+            // undo copying to scratch
+            if let Chunk::Scratch(start, _) = chunk {
+                self.scratch_len = start;
+            }
+            return false;
+        }
+        self.chunks[self.len] = chunk;
+        self.len += 1;
+        self.bytes += data.bytes().len();
+        true
+    }
+}
+
+/// Point from which interrupted formatting can be continued
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct ResumePoint {
+    /// Position of item (literal, `%%` or specification) in template.
+    ///
+    /// Equal to length of template for output produced after it (e.g. extra arguments)
+    pub(crate) pos: usize,
+    /// Index of next argument before item was processed
+    pub(crate) arg: usize,
+    /// Number of bytes of item output which were already written
+    pub(crate) offset: usize,
+}
+
 /// Formatter actually implements formatting
 pub(crate) struct Formatter<'a, H: BinSink> {
     pub(crate) sink: &'a mut H,
+    pub(crate) template: &'a [u8],
     pub(crate) args: &'a [Value<'a>],
+    pub(crate) options: &'a FormatOptions<'a>,
     pub(crate) error: Option<FormatToError<H::Err>>,
+    pub(crate) hooks: Hooks<'a, H::Err>,
+    /// Location of current item. When `error` is set, it is not updated anymore
+    pub(crate) location: ErrorLocation,
+    /// Position of conversion specifier of current specification
+    pub(crate) verb: core::ops::Range<usize>,
     pub(crate) next_arg: usize,
+    /// Number of bytes written to `sink`
+    pub(crate) written: usize,
+    /// Position in template after last visited item
+    pub(crate) pos: usize,
+    /// Current item, tracked only when formatting can be resumed
+    pub(crate) item: Option<ResumePoint>,
+    /// Number of bytes at the beginning of output which must not be written again
+    pub(crate) skip: usize,
+    /// If set, output is gathered here and passed to `sink` in batches
+    gathered: Option<Gathered<'a>>,
+}
+
+/// Runs formatter over `template`.
+///
+/// If `options` contain limits, they are checked before anything is written to `sink`.
+/// Returns number of bytes written.
+pub(crate) fn run<'a, H: BinSink>(
+    template: &'a [u8],
+    args: &'a [Value<'a>],
+    sink: &'a mut H,
+    options: &'a FormatOptions<'a>,
+    hooks: Hooks<'a, H::Err>,
+) -> Result<usize, (FormatToError<H::Err>, ErrorLocation)> {
+    let template = if options.c_strings {
+        until_nul(template)
+    } else {
+        template
+    };
+    if options.has_limits() {
+        limits::check(template, args, options)?;
+    }
+    if hooks.on_error.is_none() {
+        validate(template, args, options)?;
+    }
+    run_unchecked(template, args, sink, options, hooks)
+}
+
+/// Same as `run` without hooks, but writes to new `Vec`, which is allocated only once.
+///
+/// Dry run done to check limits or atomic mode gives capacity of output, otherwise
+/// output is measured first.
+#[cfg(feature = "alloc")]
+pub(crate) fn run_to_vec(
+    template: &[u8],
+    args: &[Value],
+    options: &FormatOptions,
+) -> Result<Vec<u8>, crate::FormatFnError> {
+    let template = if options.c_strings {
+        until_nul(template)
+    } else {
+        template
+    };
+    let mut capacity = None;
+    if options.has_limits() {
+        match limits::check(template, args, options) {
+            Ok(len) => capacity = len,
+            Err((err, loc)) => return Err(crate::FormatFnError(err, Vec::new(), loc)),
+        }
+    }
+    match validate(template, args, options) {
+        Ok(len) => capacity = len.or(capacity),
+        Err((err, loc)) => return Err(crate::FormatFnError(err, Vec::new(), loc)),
+    }
+    let capacity = capacity.unwrap_or_else(|| {
+        let measure_options = FormatOptions {
+            // warning must be issued only once
+            excess_args: match options.excess_args {
+                ExcessArgsPolicy::Warn(_) => ExcessArgsPolicy::Ignore,
+                policy => policy,
+            },
+            ..options.clone()
+        };
+        let res = run_unchecked(
+            template,
+            args,
+            &mut CountSink(0),
+            &measure_options,
+            Hooks::default(),
+        );
+        // if measuring fails, formatting will fail too, and partial output is usually small
+        res.unwrap_or(0)
+    });
+    let mut buf = crate::VecSink(Vec::with_capacity(capacity));
+    match run_unchecked(template, args, &mut buf, options, Hooks::default()) {
+        Ok(_) => Ok(buf.0),
+        Err((err, loc)) => Err(crate::FormatFnError(err, buf.0, loc)),
+    }
+}
+
+/// In atomic mode, formats without output to find errors before anything is written.
+///
+/// Returns length of output, if dry run was done.
+fn validate<E>(
+    template: &[u8],
+    args: &[Value],
+    options: &FormatOptions,
+) -> Result<Option<usize>, (FormatToError<E>, ErrorLocation)> {
+    if !options.atomic || options.inline_errors {
+        return Ok(None);
+    }
+    let dry_options;
+    let options = match options.excess_args {
+        // warning is issued by actual run
+        ExcessArgsPolicy::Warn(_) => {
+            dry_options = FormatOptions {
+                excess_args: ExcessArgsPolicy::Ignore,
+                ..options.clone()
+            };
+            &dry_options
+        }
+        _ => options,
+    };
+    match run_unchecked(template, args, &mut CountSink(0), options, Hooks::default()) {
+        Ok(len) => Ok(Some(len)),
+        Err((err, loc)) => Err((err.map_sink(|never| match never {}), loc)),
+    }
+}
+
+/// Same as `run`, but does not check limits and C-string mode
+pub(crate) fn run_unchecked<'a, H: BinSink>(
+    template: &'a [u8],
+    args: &'a [Value<'a>],
+    sink: &'a mut H,
+    options: &'a FormatOptions<'a>,
+    hooks: Hooks<'a, H::Err>,
+) -> Result<usize, (FormatToError<H::Err>, ErrorLocation)> {
+    let mut fmt = Formatter::new(sink, template, args, options, hooks);
+    if fmt.sink.is_vectored() {
+        fmt.gathered = Some(Gathered::new());
+    }
+    visit::visit_with(template, &options.parse_config(), &mut fmt);
+    if !fmt.had_error() {
+        fmt.finish();
+    }
+    // output produced before error is still written
+    let _ = fmt.flush();
+    match fmt.error {
+        Some(err) => Err((err, fmt.location)),
+        None => Ok(fmt.written),
+    }
+}
+
+/// Continues formatting from point `from`, writing only output which follows it.
+///
+/// If `from` is `None`, formatting starts from the beginning, and limits and atomic mode are
+/// checked first, like in `run`. They are not checked again when formatting is resumed.
+///
+/// Output is not gathered and padding is written in chunks, so that resume point of sink
+/// error is exact.
+/// On error, also returns point from which formatting can be resumed.
+pub(crate) fn resume<'a, H: BinSink>(
+    template: &'a [u8],
+    args: &'a [Value<'a>],
+    sink: &'a mut H,
+    options: &'a FormatOptions<'a>,
+    from: Option<ResumePoint>,
+) -> Result<usize, (FormatToError<H::Err>, ResumePoint)> {
+    let template = if options.c_strings {
+        until_nul(template)
+    } else {
+        template
+    };
+    let from = match from {
+        Some(from) => from,
+        None => {
+            let start = ResumePoint::default();
+            if options.has_limits() {
+                limits::check(template, args, options).map_err(|(err, _)| (err, start))?;
+            }
+            validate(template, args, options).map_err(|(err, _)| (err, start))?;
+            start
+        }
+    };
+    let mut fmt = Formatter::new(sink, template, args, options, Hooks::default());
+    fmt.next_arg = from.arg;
+    fmt.pos = from.pos;
+    fmt.item = Some(from);
+    fmt.skip = from.offset;
+    crate::parser::do_visit(template, from.pos, &options.parse_config(), &mut fmt);
+    if !fmt.had_error() {
+        fmt.finish();
+    }
+    match fmt.error {
+        Some(err) => Err((err, fmt.item.unwrap_or(from))),
+        None => Ok(fmt.written),
+    }
 }
 
 impl<'a, H: BinSink> Formatter<'a, H> {
+    /// Creates formatter which starts from the beginning of `template`
+    pub(crate) fn new(
+        sink: &'a mut H,
+        template: &'a [u8],
+        args: &'a [Value<'a>],
+        options: &'a FormatOptions<'a>,
+        hooks: Hooks<'a, H::Err>,
+    ) -> Self {
+        Formatter {
+            sink,
+            template,
+            args,
+            options,
+            error: None,
+            hooks,
+            location: ErrorLocation::default(),
+            verb: 0..0,
+            next_arg: 0,
+            written: 0,
+            pos: 0,
+            item: None,
+            skip: 0,
+            gathered: None,
+        }
+    }
+
     fn had_error(&self) -> bool {
         self.error.is_some()
     }
 
+    /// Reports error in current conversion specification.
+    ///
+    /// In recovery mode, error is passed to handler and placeholder is written instead of
+    /// specification. With `inline_errors`, error description is written instead of
+    /// specification. Otherwise formatting is stopped.
+    fn fail(&mut self, err: FormatToError<H::Err>) {
+        if self.options.inline_errors {
+            self.write_inline_error(&err);
+        }
+        let handler = match &mut self.hooks.on_error {
+            Some(handler) => handler,
+            None if self.options.inline_errors => return,
+            None => {
+                self.error = Some(err);
+                return;
+            }
+        };
+        handler(err, self.location.clone());
+        if self.options.inline_errors {
+            return;
+        }
+        let template = self.template;
+        let placeholder = match self.options.placeholder {
+            Some(placeholder) => placeholder,
+            None => self
+                .location
+                .span
+                .clone()
+                .map_or(&[][..], |span| &template[span]),
+        };
+        let _ = self.write_output(Output::Borrowed(placeholder));
+    }
+
+    /// Writes error description in style of Go's `fmt`, e.g. `%!d(string=hi)`
+    fn write_inline_error(&mut self, err: &FormatToError<H::Err>) {
+        let template = self.template;
+        let verb = &template[self.verb.clone()];
+        let arg = self.location.arg.and_then(|i| self.args.get(i));
+        // `None` means that argument is described
+        let (verb, detail): (&[u8], Option<&[u8]>) = match err {
+            FormatToError::Spec(ParseError::InvalidWidth(_)) => (b"", Some(b"BADWIDTH")),
+            FormatToError::Spec(ParseError::InvalidPrec(_)) => (b"", Some(b"BADPREC")),
+            _ if verb.is_empty() => (b"", Some(b"NOVERB")),
+            FormatToError::NotEnoughArguments => (verb, Some(b"MISSING")),
+            _ if arg.is_some() => (verb, None),
+            _ => (verb, Some(b"BADSPEC")),
+        };
+        if !self.call_handler(b"%!") || !self.call_handler(verb) || !self.call_handler(b"(") {
+            return;
+        }
+        let ok = match (detail, arg) {
+            (None, Some(arg)) => self.write_typed_value(arg),
+            (detail, _) => self.call_handler(detail.unwrap_or_default()),
+        };
+        if ok {
+            let _ = self.call_handler(b")");
+        }
+    }
+
+    /// Writes `type=value`, as Go's `fmt` does in error descriptions
+    #[must_use]
+    fn write_typed_value(&mut self, arg: &Value) -> bool {
+        match *arg {
+            Value::Int(x) => {
+                let mut buf = itoa::Buffer::new();
+                self.call_handler(b"int=") && self.call_handler(buf.format(x).as_bytes())
+            }
+            Value::String(s) => self.call_handler(b"string=") && self.call_handler(s),
+        }
+    }
+
+    /// Deals with arguments which were not consumed by format string
+    fn finish(&mut self) {
+        if self.next_arg >= self.args.len() {
+            return;
+        }
+        self.start_item(self.template.len());
+        self.location = ErrorLocation {
+            span: None,
+            arg: Some(self.next_arg),
+        };
+        if self.options.inline_errors {
+            self.write_extra_args();
+            if self.had_error() {
+                return;
+            }
+        }
+        match self.options.excess_args {
+            ExcessArgsPolicy::Ignore => {}
+            ExcessArgsPolicy::Warn(warn) => warn(self.next_arg, self.args.len()),
+            ExcessArgsPolicy::Error => match &mut self.hooks.on_error {
+                Some(handler) => handler(FormatToError::ExcessArgs, self.location.clone()),
+                None if self.options.inline_errors => {}
+                None => self.error = Some(FormatToError::ExcessArgs),
+            },
+        }
+    }
+
+    /// Writes arguments which were not used by format string, e.g. `%!(EXTRA int=3)`
+    fn write_extra_args(&mut self) {
+        let args = self.args;
+        if self.next_arg >= args.len() {
+            return;
+        }
+        if !self.call_handler(b"%!(EXTRA ") {
+            return;
+        }
+        for (i, arg) in args[self.next_arg..].iter().enumerate() {
+            if i != 0 && !self.call_handler(b", ") {
+                return;
+            }
+            if !self.write_typed_value(arg) {
+                return;
+            }
+        }
+        let _ = self.call_handler(b")");
+    }
+
+    /// Writes temporary data
     #[must_use]
     fn call_handler(&mut self, b: &[u8]) -> bool {
+        self.write_output(Output::Temporary(b))
+    }
+
+    /// Starts new item at position `pos` of template
+    fn start_item(&mut self, pos: usize) {
+        let arg = self.next_arg;
+        if let Some(item) = &mut self.item {
+            *item = ResumePoint {
+                pos,
+                arg,
+                offset: 0,
+            };
+        }
+    }
+
+    /// Accounts `cnt` bytes of output of current item
+    fn advance_item(&mut self, cnt: usize) {
+        if let Some(item) = &mut self.item {
+            item.offset += cnt;
+        }
+    }
+
+    /// Skips beginning of output which was written before formatting was resumed.
+    ///
+    /// Returns number of skipped bytes out of `len`
+    fn skip_output(&mut self, len: usize) -> usize {
+        let skipped = core::cmp::min(self.skip, len);
+        self.skip -= skipped;
+        self.advance_item(skipped);
+        skipped
+    }
+
+    #[must_use]
+    fn write_output(&mut self, data: Output<'a, '_>) -> bool {
+        let skipped = self.skip_output(data.bytes().len());
+        if skipped != 0 && skipped == data.bytes().len() {
+            return true;
+        }
+        let data = data.advance(skipped);
+        if let Some(gathered) = &mut self.gathered {
+            if gathered.push(data) {
+                return true;
+            }
+            if !self.flush() {
+                return false;
+            }
+            if self.gathered.as_mut().is_some_and(|g| g.push(data)) {
+                return true;
+            }
+            // too large for scratch buffer, so it is written directly
+        }
+        let b = data.bytes();
         match self.sink.put(b) {
-            Ok(()) => true,
+            Ok(()) => {
+                self.written += b.len();
+                self.advance_item(b.len());
+                true
+            }
             Err(e) => {
-                self.error = Some(FormatToError::Sink(e));
+                self.error = Some(FormatToError::Sink(e, self.written));
+                false
+            }
+        }
+    }
+
+    /// Passes gathered output to sink
+    #[must_use]
+    fn flush(&mut self) -> bool {
+        let gathered = match &mut self.gathered {
+            Some(gathered) if gathered.len != 0 => gathered,
+            _ => return true,
+        };
+        let mut slices: [&[u8]; MAX_CHUNKS] = [&[]; MAX_CHUNKS];
+        for (slice, chunk) in slices.iter_mut().zip(&gathered.chunks[..gathered.len]) {
+            *slice = match *chunk {
+                Chunk::Borrowed(b) => b,
+                Chunk::Scratch(start, end) => &gathered.scratch[start..end],
+            };
+        }
+        let res = self.sink.put_vectored(&slices[..gathered.len]);
+        let bytes = gathered.bytes;
+        *gathered = Gathered::new();
+        match res {
+            Ok(()) => {
+                self.written += bytes;
+                true
+            }
+            Err(e) => {
+                // error which stopped formatting is more important
+                if self.error.is_none() {
+                    self.error = Some(FormatToError::Sink(e, self.written));
+                }
                 false
             }
         }
@@ -89,82 +848,117 @@ impl<'a, H: BinSink> Formatter<'a, H> {
     #[must_use]
     fn write_padding(&mut self, c: u8, cnt: usize) -> bool {
         debug_assert!(c == b'0' || c == b' ');
-        let sl = &[c];
-        for _ in 0..cnt {
-            if !self.call_handler(sl) {
-                return false;
+        let cnt = cnt - self.skip_output(cnt);
+        if cnt == 0 {
+            return true;
+        }
+        let padding = if c == b'0' { &ZEROS } else { &SPACES };
+        if self.gathered.is_some() && cnt <= SPACES.len() {
+            return self.write_output(Output::Borrowed(&padding[..cnt]));
+        }
+        if self.item.is_some() {
+            // `put_repeat` may fail after writing part of padding
+            let mut left = cnt;
+            while left != 0 {
+                let len = core::cmp::min(left, padding.len());
+                if !self.write_output(Output::Borrowed(&padding[..len])) {
+                    return false;
+                }
+                left -= len;
+            }
+            return true;
+        }
+        if !self.flush() {
+            return false;
+        }
+        match self.sink.put_repeat(c, cnt) {
+            Ok(()) => {
+                self.written += cnt;
+                self.advance_item(cnt);
+                true
+            }
+            Err(e) => {
+                self.error = Some(FormatToError::Sink(e, self.written));
+                false
             }
         }
-        true
     }
 
     /// helper for format_* methods to deal with padding
-    fn write_data(&mut self, data: &[u8], spec: ParsedConversionSpecification) {
-        let padding_size = if data.len() < spec.min_width {
-            spec.min_width - data.len()
-        } else {
-            0
-        };
+    fn write_data(&mut self, data: Output<'a, '_>, spec: ParsedConversionSpecification) {
+        let padding_size = spec.min_width.saturating_sub(data.bytes().len());
 
         let pad_char = if spec.flags.pad_zero { b'0' } else { b' ' };
 
-        if !spec.flags.adj_left {
-            if !self.write_padding(pad_char, padding_size) {
-                return;
-            }
+        if !spec.flags.adj_left && !self.write_padding(pad_char, padding_size) {
+            return;
         }
 
-        if !self.call_handler(data) {
+        if !self.write_output(data) {
             return;
         }
 
         if spec.flags.adj_left {
-            if !self.write_padding(pad_char, padding_size) {
-                return;
-            }
+            let _ = self.write_padding(pad_char, padding_size);
         }
     }
 
     fn format_int(&mut self, x: i128, spec: ParsedConversionSpecification) {
-        match spec.conv_kind {
+        let signed = match spec.conv_kind {
+            ConvKind::String if self.options.coerce_args => {
+                let mut buf = itoa::Buffer::new();
+                self.format_bytes(Output::Temporary(buf.format(x).as_bytes()), spec);
+                return;
+            }
             ConvKind::String => {
-                self.error = Some(FormatToError::BadType);
+                self.fail(FormatToError::BadType);
                 return;
             }
-            ConvKind::SignDecInt => {
-                // check limits
-                let (low_bound, up_bound) = match spec.len_modifier {
-                    LenModifier::Shorter => (i8::min_value() as i128, i8::max_value() as i128),
-                    LenModifier::Short => (i16::min_value() as i128, i16::max_value() as i128),
-                    LenModifier::None => (i32::min_value() as i128, i32::max_value() as i128),
-                    LenModifier::Long | LenModifier::Longer => {
-                        (i64::min_value() as i128, i64::max_value() as i128)
-                    }
-                    LenModifier::Longest => (i128::min_value(), i128::max_value()),
-                    LenModifier::Size => (isize::min_value() as i128, isize::max_value() as i128),
-                    _ => {
-                        self.error = Some(FormatToError::Unsupported);
-                        return;
-                    }
-                };
-
-                if x < low_bound || up_bound < x {
-                    self.error = Some(FormatToError::NumOverflow);
-                    return;
-                }
+            ConvKind::SignDecInt => true,
+            ConvKind::UnsignedDecInt => false,
+        };
+        let bits = match spec.len_modifier {
+            LenModifier::Shorter => 8,
+            LenModifier::Short => 16,
+            LenModifier::None => 32,
+            LenModifier::Long | LenModifier::Longer => 64,
+            LenModifier::Longest => 128,
+            LenModifier::Size => usize::BITS,
+            _ => {
+                self.fail(FormatToError::Unsupported);
+                return;
+            }
+        };
 
-                let mut buf = itoa::Buffer::new();
-                let data = buf.format(x).as_bytes();
-                self.write_data(data, spec);
+        let mut buf = itoa::Buffer::new();
+        let data = match convert_int(x, bits, signed, self.options.overflow) {
+            Some(Int::Signed(x)) => buf.format(x),
+            Some(Int::Unsigned(x)) => buf.format(x),
+            None => {
+                self.fail(FormatToError::NumOverflow);
+                return;
+            }
+        };
+        match self.options.locale {
+            Some(locale) if spec.flags.comma_groups => {
+                let mut grouped = [0; GROUPED_INT_LEN];
+                let len = group_digits(data.as_bytes(), locale, &mut grouped);
+                self.write_data(Output::Temporary(&grouped[..len]), spec);
             }
+            _ => self.write_data(Output::Temporary(data.as_bytes()), spec),
         }
     }
 
-    fn format_bytes(&mut self, b: &[u8], spec: ParsedConversionSpecification) {
+    fn format_bytes(&mut self, b: Output<'a, '_>, spec: ParsedConversionSpecification) {
         match spec.conv_kind {
-            ConvKind::SignDecInt => {
-                self.error = Some(FormatToError::BadType);
-                return;
+            ConvKind::SignDecInt | ConvKind::UnsignedDecInt if self.options.coerce_args => {
+                match coerce_to_int(b.bytes()) {
+                    Ok(x) => self.format_int(x, spec),
+                    Err(e) => self.fail(FormatToError::Coercion(e)),
+                }
+            }
+            ConvKind::SignDecInt | ConvKind::UnsignedDecInt => {
+                self.fail(FormatToError::BadType);
             }
             ConvKind::String => {
                 if spec.flags.alt
@@ -172,54 +966,103 @@ impl<'a, H: BinSink> Formatter<'a, H> {
                     || spec.flags.comma_groups
                     || spec.flags.alt_digits
                 {
-                    self.error = Some(FormatToError::Invalid);
+                    self.fail(FormatToError::Invalid);
                     return;
                 }
-                let prec = spec.prec.unwrap_or(b.len());
-
-                let write_part = &b[..std::cmp::min(b.len(), prec)];
-                self.write_data(write_part, spec);
+                let mut len = b.bytes().len();
+                if self.options.c_strings {
+                    len = until_nul(b.bytes()).len();
+                }
+                if let Some(prec) = spec.prec {
+                    len = std::cmp::min(len, prec);
+                }
+                self.write_data(b.truncate(len), spec);
             }
         }
     }
 
+    /// With `inline_errors`, specification which can not be formatted still consumes an
+    /// argument, like in Go
+    fn skip_arg(&mut self) {
+        if !self.options.inline_errors || self.verb.is_empty() {
+            return;
+        }
+        self.location.arg = Some(self.next_arg);
+        if self.next_arg < self.args.len() {
+            self.consume_arg();
+        }
+    }
+
+    /// Advances to next argument, notifying `on_arg` hook
+    fn consume_arg(&mut self) {
+        if let (Some(on_arg), Some(span)) = (&mut self.hooks.on_arg, &self.location.span) {
+            on_arg(self.next_arg, span.clone());
+        }
+        self.next_arg += 1;
+    }
+
     fn format(&mut self, spec: ParsedConversionSpecification) {
+        self.location.arg = Some(self.next_arg);
         if self.next_arg == self.args.len() {
-            self.error = Some(FormatToError::NotEnoughArguments);
+            self.fail(FormatToError::NotEnoughArguments);
             return;
         }
         let arg = &self.args[self.next_arg];
-        self.next_arg += 1;
+        self.consume_arg();
         match *arg {
             Value::Int(x) => self.format_int(x, spec),
-            Value::String(bytes) => self.format_bytes(bytes, spec),
+            Value::String(bytes) => self.format_bytes(Output::Borrowed(bytes), spec),
         }
     }
 }
 
 impl<'a, H: BinSink> FormatStringVisitor for Formatter<'a, H> {
-    fn visit_bytes(&mut self, b: &[u8]) {
+    fn visit_literal(&mut self, span: core::ops::Range<usize>, _: &[u8]) {
         if self.had_error() {
             return;
         }
-        if let Err(e) = self.sink.put(b) {
-            self.error = Some(FormatToError::Sink(e));
+        self.location = ErrorLocation::default();
+        self.start_item(span.start);
+        self.pos = span.end;
+        // literal is borrowed from template, so it can be gathered without copying
+        let template = self.template;
+        let _ = self.write_output(Output::Borrowed(&template[span]));
+    }
+
+    fn visit_escaped_percent(&mut self) {
+        if self.had_error() {
+            return;
         }
+        self.location = ErrorLocation::default();
+        self.start_item(self.pos);
+        self.pos += 2;
+        let _ = self.write_output(Output::Borrowed(b"%"));
     }
 
     fn visit_specification(&mut self, spec: ConversionSpecification) {
         if self.had_error() {
             return;
         }
-        let hi_spec = match ParsedConversionSpecification::try_parse(spec) {
+        self.start_item(spec.span.start);
+        self.pos = spec.span.end;
+        self.location = ErrorLocation {
+            span: Some(spec.span.clone()),
+            arg: None,
+        };
+        self.verb = spec.span.end - spec.specifier.len()..spec.span.end;
+        let hi_spec = match self.options.parse_spec(spec) {
             Ok(x) => x,
             Err(e) => {
-                self.error = Some(FormatToError::Spec(e));
+                if let ParseError::UnknownSpecifier | ParseError::Unsupported = e {
+                    self.skip_arg();
+                }
+                self.fail(FormatToError::Spec(e));
                 return;
             }
         };
-        if !hi_spec.is_supported() {
-            self.error = Some(FormatToError::Unsupported);
+        if !hi_spec.is_supported(self.options.locale.is_some()) {
+            self.skip_arg();
+            self.fail(FormatToError::Unsupported);
             return;
         }
         self.format(hi_spec);
@@ -10,7 +10,12 @@ use crate::visit::ConversionSpecification;
 /// if you are using hand-crafted `ConversionSpecification`.
 #[derive(Debug)]
 pub enum ParseError {
+    /// Specification is followed by end of format string or by next `%`
     MissingSpecifier,
+    /// Conversion specifier is not known.
+    ///
+    /// Parser takes any byte after flags, width, precision and length modifier as
+    /// specifier, so `%y` is reported this way, and `y` is not copied to output.
     UnknownSpecifier,
     UnknownFlag(u8),
     DuplicateFlag(u8),
@@ -19,6 +24,16 @@ pub enum ParseError {
     InvalidWidth(Option<core::num::ParseIntError>),
     UnknownLenModifier,
     Unsupported,
+    /// Format string ends with single `%`
+    DanglingPercent,
+    /// Format string ended before conversion specifier
+    UnterminatedSpec,
+    /// Precision contains more than one `.`
+    RepeatedPrecision,
+    /// Length modifier can not be used with conversion specifier
+    IncompatibleLenModifier,
+    /// Flag can not be used with conversion specifier
+    IncompatibleFlag(u8),
 }
 
 impl ParseError {
@@ -34,6 +49,13 @@ impl ParseError {
                 "conversion specifier contains unknown length modifier"
             }
             ParseError::Unsupported => "this feature is not supported",
+            ParseError::DanglingPercent => "format string ends with `%`",
+            ParseError::UnterminatedSpec => "conversion specification is not terminated",
+            ParseError::RepeatedPrecision => "conversion specifier contains several precisions",
+            ParseError::IncompatibleLenModifier => {
+                "length modifier is not compatible with conversion specifier"
+            }
+            ParseError::IncompatibleFlag(_) => "flag is not compatible with conversion specifier",
         }
     }
 }
@@ -60,13 +82,12 @@ impl ParsedConversionSpecification {
     pub fn try_parse(
         spec: ConversionSpecification,
     ) -> Result<ParsedConversionSpecification, ParseError> {
-        let specifier = ConvKind::from_bytes(spec.specifier).ok_or_else(|| {
-            if spec.specifier.is_empty() {
+        let specifier =
+            ConvKind::from_bytes(spec.specifier).ok_or(if spec.specifier.is_empty() {
                 ParseError::MissingSpecifier
             } else {
                 ParseError::UnknownSpecifier
-            }
-        })?;
+            })?;
         let flags = ConvFlags::from_bytes(spec.flags)?;
 
         let len_mod = LenModifier::from_bytes(spec.length)?;
@@ -105,14 +126,122 @@ impl ParsedConversionSpecification {
         })
     }
 
-    pub(crate) fn is_supported(&self) -> bool {
-        self.flags.is_supported()
+    /// Same as `try_parse`, but additionally checks that `spec` conforms to POSIX grammar.
+    ///
+    /// Specifications which are undefined in C (e.g. `%hhs` or `%#d`) are rejected, and
+    /// conversions which are valid, but not implemented by `formatf`, are reported as
+    /// `ParseError::Unsupported`.
+    ///
+    /// `spec` is expected to be produced by parser in strict mode
+    /// (see [`ParseConfig`](../visit/struct.ParseConfig.html)).
+    pub fn try_parse_strict(
+        spec: ConversionSpecification,
+    ) -> Result<ParsedConversionSpecification, ParseError> {
+        Self::try_parse_dialect(spec, Dialect::Posix)
+    }
+
+    /// Same as `try_parse_strict`, but checks `spec` against grammar of given `dialect`
+    pub fn try_parse_dialect(
+        spec: ConversionSpecification,
+        dialect: Dialect,
+    ) -> Result<ParsedConversionSpecification, ParseError> {
+        check_grammar(&spec, dialect)?;
+        let mut spec = spec;
+        if spec.precision == b"." {
+            // C treats lone `.` as zero precision
+            spec.precision = b".0";
+        }
+        Self::try_parse(spec).map_err(|err| match err {
+            ParseError::UnknownSpecifier => ParseError::Unsupported,
+            other => other,
+        })
+    }
+
+    /// Checks that `formatf` can format this specification.
+    ///
+    /// `grouping` tells whether thousands grouping (`'` flag) is available
+    pub(crate) fn is_supported(&self, grouping: bool) -> bool {
+        self.flags.is_supported(grouping)
+    }
+}
+
+/// Variant of `printf` format string grammar
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Dialect {
+    /// ISO C99.
+    C99,
+    /// POSIX, which adds `'` flag and `%C`/`%S` conversions to C99. This is default.
+    #[default]
+    Posix,
+    /// GNU libc, which adds `I` flag, `Z` and `q` length modifiers and `%m` conversion
+    /// to POSIX.
+    Gnu,
+}
+
+fn check_grammar(spec: &ConversionSpecification, dialect: Dialect) -> Result<(), ParseError> {
+    let posix = dialect != Dialect::C99;
+    let gnu = dialect == Dialect::Gnu;
+    let conv = match *spec.specifier {
+        [] => {
+            let all_empty = spec.flags.is_empty()
+                && spec.field_width.is_empty()
+                && spec.precision.is_empty()
+                && spec.length.is_empty();
+            return Err(if all_empty {
+                ParseError::DanglingPercent
+            } else {
+                ParseError::UnterminatedSpec
+            });
+        }
+        [conv] => conv,
+        _ => return Err(ParseError::UnknownSpecifier),
+    };
+    if spec.precision.iter().filter(|&&c| c == b'.').count() > 1 {
+        return Err(ParseError::RepeatedPrecision);
+    }
+    let is_int = b"diouxXn".contains(&conv);
+    let is_float = b"aAeEfFgG".contains(&conv);
+    let is_other = match conv {
+        b'c' | b's' | b'p' => true,
+        b'C' | b'S' => posix,
+        b'm' => gnu,
+        _ => false,
+    };
+    if !is_int && !is_float && !is_other {
+        return Err(ParseError::UnknownSpecifier);
+    }
+    let len_ok = match spec.length {
+        b"" => true,
+        b"hh" | b"h" | b"ll" | b"j" | b"z" | b"t" => is_int,
+        b"Z" | b"q" if gnu => is_int,
+        b"l" => is_int || is_float || conv == b'c' || conv == b's',
+        b"L" => is_float,
+        _ => return Err(ParseError::UnknownLenModifier),
+    };
+    if !len_ok {
+        return Err(ParseError::IncompatibleLenModifier);
+    }
+    for &flag in spec.flags {
+        let flag_ok = match flag {
+            b'#' => b"oxXaAeEfFgG".contains(&conv),
+            b'0' => (is_int && conv != b'n') || is_float,
+            b'\'' if posix => b"diufFgG".contains(&conv),
+            b'I' if gnu => b"diufFgG".contains(&conv),
+            b'-' | b' ' | b'+' => true,
+            _ => return Err(ParseError::UnknownFlag(flag)),
+        };
+        if !flag_ok {
+            return Err(ParseError::IncompatibleFlag(flag));
+        }
     }
+    Ok(())
 }
 
 /// Conversion specifier
 pub enum ConvKind {
     SignDecInt,
+    /// Corresponds to `u`.
+    UnsignedDecInt,
     String,
 }
 
@@ -121,6 +250,7 @@ impl ConvKind {
         use ConvKind::*;
         match b {
             b"d" | b"i" => Some(SignDecInt),
+            b"u" => Some(UnsignedDecInt),
             b"s" => Some(String),
             _ => None,
         }
@@ -132,7 +262,7 @@ pub enum LenModifier {
     None,
     /// Corresponds to `l`.
     Long,
-    /// Corresponds to `ll`.
+    /// Corresponds to `ll` (or `q`).
     Longer,
     /// Corresponds to `j`.
     Longest,
@@ -153,7 +283,7 @@ impl LenModifier {
         use LenModifier::*;
         match b {
             b"l" => Ok(Long),
-            b"ll" => Ok(Longer),
+            b"ll" | b"q" => Ok(Longer),
             b"j" => Ok(Longest),
             b"h" => Ok(Short),
             b"hh" => Ok(Shorter),
@@ -208,8 +338,8 @@ impl ConvFlags {
     }
 
     /// Checks that all triggered flags are supported by `formatf`
-    fn is_supported(&self) -> bool {
-        !self.alt_digits && !self.comma_groups
+    fn is_supported(&self, grouping: bool) -> bool {
+        !self.alt_digits && (grouping || !self.comma_groups)
     }
 }
 
@@ -0,0 +1,251 @@
+//! Formatting which can be interrupted by sink and resumed later
+
+use crate::{
+    format::{self, ResumePoint},
+    BinSink, FormatOptions, FormatToError, Value,
+};
+
+/// Formatting of `template` which survives sink errors.
+///
+/// When sink fails (e.g. non-blocking socket returns `WouldBlock`), job remembers position
+/// in template, current argument and number of bytes of current conversion which were
+/// already written. Next call to [`run`] renders current item again, skips bytes which were
+/// written, and continues, so complete output is the same as output of single `format_to`.
+///
+/// Sink must either accept whole chunk passed to `put`, or fail without writing any part of
+/// it. Otherwise part of chunk is written twice.
+///
+/// ```rust
+/// use formatf::{BinSink, FormatJob, Value};
+///
+/// /// Accepts at most 4 bytes until counter is reset
+/// struct Pipe(Vec<u8>, usize);
+///
+/// impl BinSink for Pipe {
+///     type Err = ();
+///     fn put(&mut self, data: &[u8]) -> Result<(), ()> {
+///         if self.1 + data.len() > 4 {
+///             return Err(());
+///         }
+///         self.1 += data.len();
+///         self.0.extend_from_slice(data);
+///         Ok(())
+///     }
+/// }
+///
+/// let args = [Value::String(b"job"), Value::Int(7)];
+/// let mut job = FormatJob::new(b"%s #%d", &args);
+/// let mut pipe = Pipe(Vec::new(), 0);
+/// while job.run(&mut pipe).is_err() {
+///     // wait until sink is ready
+///     pipe.1 = 0;
+/// }
+/// assert_eq!(pipe.0, b"job #7");
+/// assert!(job.is_finished());
+/// ```
+///
+/// [`run`]: #method.run
+#[derive(Clone)]
+pub struct FormatJob<'a> {
+    template: &'a [u8],
+    args: &'a [Value<'a>],
+    options: Option<&'a FormatOptions<'a>>,
+    point: ResumePoint,
+    written: usize,
+    /// Set after first run, when limits and atomic mode were checked
+    started: bool,
+    finished: bool,
+}
+
+impl<'a> FormatJob<'a> {
+    /// Creates job formatting `template` with given `args` and default options.
+    ///
+    /// Nothing is formatted until [`run`] is called.
+    ///
+    /// [`run`]: #method.run
+    pub fn new(template: &'a [u8], args: &'a [Value<'a>]) -> Self {
+        FormatJob {
+            template,
+            args,
+            options: None,
+            point: ResumePoint::default(),
+            written: 0,
+            started: false,
+            finished: false,
+        }
+    }
+
+    /// Uses given `options` instead of default ones
+    pub fn options(mut self, options: &'a FormatOptions<'a>) -> Self {
+        self.options = Some(options);
+        self
+    }
+
+    /// Formats to `sink`, continuing from where previous call stopped.
+    ///
+    /// Returns total number of bytes written by all calls. If `sink` fails, job can be run
+    /// again; number of bytes in `FormatToError::Sink` is total too. Other errors finish job.
+    ///
+    /// Does nothing if job is already finished.
+    pub fn run<H: BinSink>(&mut self, sink: &mut H) -> Result<usize, FormatToError<H::Err>> {
+        if self.finished {
+            return Ok(self.written);
+        }
+        let default_options;
+        let options = match self.options {
+            Some(options) => options,
+            None => {
+                default_options = FormatOptions::default();
+                &default_options
+            }
+        };
+        let from = if self.started { Some(self.point) } else { None };
+        self.started = true;
+        let res = format::resume(self.template, self.args, sink, options, from);
+        match res {
+            Ok(written) => {
+                self.written += written;
+                self.finished = true;
+                Ok(self.written)
+            }
+            Err((FormatToError::Sink(err, written), point)) => {
+                self.written += written;
+                self.point = point;
+                Err(FormatToError::Sink(err, self.written))
+            }
+            Err((err, _)) => {
+                self.finished = true;
+                Err(err)
+            }
+        }
+    }
+
+    /// Returns `true` if formatting completed, successfully or not
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    /// Returns total number of bytes written
+    pub fn written(&self) -> usize {
+        self.written
+    }
+
+    /// Returns position in template of item which will be formatted next.
+    ///
+    /// Equal to length of template if only output after template is left (e.g. with
+    /// `FormatOptions::inline_errors`, unused arguments)
+    pub fn position(&self) -> usize {
+        self.point.pos
+    }
+
+    /// Returns index of argument which will be consumed next
+    pub fn arg(&self) -> usize {
+        self.point.arg
+    }
+
+    /// Returns number of bytes of next item output which were already written
+    pub fn offset(&self) -> usize {
+        self.point.offset
+    }
+}
+
+#[cfg(all(test, feature = "alloc"))]
+mod tests {
+    use super::*;
+    use crate::ExcessArgsPolicy;
+
+    /// Sink which fails every `period`-th call without writing anything
+    struct Flaky {
+        data: Vec<u8>,
+        calls: usize,
+        period: usize,
+    }
+
+    impl BinSink for Flaky {
+        type Err = ();
+
+        fn put(&mut self, data: &[u8]) -> Result<(), ()> {
+            self.calls += 1;
+            if self.calls % self.period == 0 {
+                return Err(());
+            }
+            self.data.extend_from_slice(data);
+            Ok(())
+        }
+    }
+
+    fn run_to_end(job: &mut FormatJob, sink: &mut Flaky) -> Vec<(usize, usize, usize)> {
+        let mut points = Vec::new();
+        while let Err(err) = job.run(sink) {
+            assert!(matches!(err, FormatToError::Sink((), n) if n == sink.data.len()));
+            points.push((job.position(), job.arg(), job.offset()));
+        }
+        points
+    }
+
+    #[test]
+    fn resumes() {
+        let args = [Value::String(b"abc"), Value::Int(-42), Value::Int(5)];
+        let inline = FormatOptions::new().inline_errors(true);
+        let cases: [(&[u8], &FormatOptions); 4] = [
+            (b"<%s> %d%% %d", &FormatOptions::default()),
+            (b"%-70s|%0100d|%d", &FormatOptions::default()),
+            (b"%s %s", &inline),
+            (b"%5d%%", &inline),
+        ];
+        for (template, options) in cases {
+            let expected = crate::format_with(template, &args, options).unwrap();
+            for period in 2..6 {
+                let mut sink = Flaky {
+                    data: Vec::new(),
+                    calls: 0,
+                    period,
+                };
+                let mut job = FormatJob::new(template, &args).options(options);
+                run_to_end(&mut job, &mut sink);
+                assert!(job.is_finished());
+                assert_eq!(sink.data, expected);
+                assert_eq!(job.written(), expected.len());
+            }
+        }
+    }
+
+    #[test]
+    fn resume_points() {
+        let args = [Value::String(b"abc"), Value::Int(1)];
+        let mut sink = Flaky {
+            data: Vec::new(),
+            calls: 0,
+            period: 2,
+        };
+        let mut job = FormatJob::new(b"x%10s%d", &args);
+        // literal, padding of `%10s`, string, `%d`
+        let points = run_to_end(&mut job, &mut sink);
+        assert_eq!(points, [(1, 0, 0), (1, 0, 7), (5, 1, 0)]);
+        assert_eq!(sink.data, b"x       abc1");
+
+        let mut job = FormatJob::new(b"%d %d", &args);
+        assert!(matches!(job.run(&mut sink), Err(FormatToError::BadType)));
+        assert!(job.is_finished());
+    }
+
+    #[test]
+    fn checks_once() {
+        let args = [Value::Int(1), Value::Int(2), Value::Int(3)];
+        let warnings = core::cell::Cell::new(0);
+        let warn = |_, _| warnings.set(warnings.get() + 1);
+        let options = FormatOptions::new()
+            .inline_errors(true)
+            .max_output(Some(100))
+            .excess_args(ExcessArgsPolicy::Warn(&warn));
+        let mut sink = Flaky {
+            data: Vec::new(),
+            calls: 0,
+            period: 2,
+        };
+        let mut job = FormatJob::new(b"%d|", &args).options(&options);
+        assert!(!run_to_end(&mut job, &mut sink).is_empty());
+        assert_eq!(sink.data, b"1|%!(EXTRA int=2, int=3)");
+        assert_eq!(warnings.get(), 1);
+    }
+}
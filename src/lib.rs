@@ -4,6 +4,7 @@
 //! - format!()-like functionality - create new VEC with rendered template (requires `alloc` feature)
 //! - write!()-like functionality - write template to custom callback or `std::io::Write` implementation (latter requires `std` feature)
 //! - no_std / alloc-only support
+//! - formatting to asynchronous writers (requires `async` feature, which is enabled by `futures-io` and `tokio` features)
 //! - does not depend on any C libraries
 //! - safe panic-free API - if incorrect format string is provided, Error will returned instead of panic of triggering UB
 //! - bytes-oriented - format string must not be utf8
@@ -18,11 +19,55 @@ extern crate alloc;
 #[macro_use]
 extern crate pretty_assertions;
 
-use crate::format::FormatToError;
+#[cfg(feature = "futures-io")]
+pub use crate::async_sink::FuturesIoSink;
+#[cfg(feature = "tokio")]
+pub use crate::async_sink::TokioSink;
+#[cfg(feature = "async")]
+pub use crate::async_sink::{format_to_async, format_to_async_with, AsyncBinSink, ASYNC_BUF_LEN};
+#[cfg(target_os = "linux")]
+pub use crate::fd::{Errno, FdSink};
+pub use crate::{
+    array::{
+        format_array, format_array_with, ArrayBuf, FixedString, FixedStringError, FixedStringSink,
+    },
+    combinators::{Fallback, MapErr, Tee, TeeError},
+    fmt_write::{FmtWriteSink, Formatted, NonUtf8},
+    format::{ErrorLocation, FormatToError},
+    high::Dialect,
+    job::FormatJob,
+    options::{ExcessArgsPolicy, FormatOptions, Locale, OverflowPolicy},
+    ring::{Records, RingSink},
+    truncating::{snprintf, snprintf_with, TruncatingSink, Written},
+};
+#[cfg(feature = "alloc")]
+pub use crate::{
+    sanitize::{sanitize, SanitizeChange, SanitizeChangeKind, Sanitized},
+    usage::{args_usage, ArgsUsage},
+};
 
+mod array;
+#[cfg(feature = "async")]
+mod async_sink;
+mod combinators;
+#[cfg(feature = "alloc")]
+pub mod diag;
+#[cfg(target_os = "linux")]
+mod fd;
+mod fmt_write;
 mod format;
 pub mod high;
+mod job;
+mod limits;
+mod options;
 mod parser;
+mod ring;
+#[cfg(feature = "alloc")]
+mod sanitize;
+mod truncating;
+#[cfg(feature = "alloc")]
+mod usage;
+mod utf8;
 pub mod visit;
 
 pub enum Value<'a> {
@@ -30,12 +75,26 @@ pub enum Value<'a> {
     String(&'a [u8]),
 }
 
+impl Value<'_> {
+    /// Returns name of variant, suitable for error messages
+    pub fn kind_name(&self) -> &'static str {
+        match self {
+            Value::Int(_) => "Int",
+            Value::String(_) => "String",
+        }
+    }
+}
+
 /// Error returned by [`format`]
 ///
 /// [`format`]: ./fn.format.html
 #[cfg(feature = "alloc")]
 #[derive(Debug)]
-pub struct FormatFnError(FormatToError<core::convert::Infallible>, Vec<u8>);
+pub struct FormatFnError(
+    FormatToError<core::convert::Infallible>,
+    Vec<u8>,
+    ErrorLocation,
+);
 
 #[cfg(feature = "alloc")]
 impl FormatFnError {
@@ -49,6 +108,22 @@ impl FormatFnError {
         self.0
     }
 
+    /// Returns location of error in format string and argument list
+    pub fn location(&self) -> &ErrorLocation {
+        &self.2
+    }
+
+    /// Renders human-readable description of error, pointing into `template`.
+    ///
+    /// `template` and `args` must be the ones passed to [`format`].
+    /// See [`diag`] module for details.
+    ///
+    /// [`format`]: ./fn.format.html
+    /// [`diag`]: ./diag/index.html
+    pub fn render(&self, template: &[u8], args: &[Value]) -> String {
+        diag::render(template, args, &self.0, &self.2)
+    }
+
     /// Returns reference to buffer that was already created when error occured
     pub fn buf(&self) -> &[u8] {
         &self.1
@@ -84,15 +159,68 @@ impl FormatFnError {
 /// ```
 #[cfg(feature = "alloc")]
 pub fn format(template: &[u8], args: &[Value]) -> Result<Vec<u8>, FormatFnError> {
-    let mut buf = VecSink(Vec::new());
-    match format_to(template, args, &mut buf) {
-        Ok(()) => Ok(buf.0),
-        Err(e) => Err(FormatFnError(e, buf.0)),
-    }
+    format_with(template, args, &FormatOptions::default())
+}
+
+/// Same as [`format`], but uses given `options`
+///
+/// ```rust
+/// use formatf::{format_with, FormatOptions, Value};
+/// let options = FormatOptions {
+///     strict: true,
+///     ..Default::default()
+/// };
+/// assert!(format_with(b"%.5.3d", &[Value::Int(42)], &options).is_err());
+/// ```
+///
+/// [`format`]: ./fn.format.html
+#[cfg(feature = "alloc")]
+pub fn format_with(
+    template: &[u8],
+    args: &[Value],
+    options: &FormatOptions,
+) -> Result<Vec<u8>, FormatFnError> {
+    format::run_to_vec(template, args, options)
+}
+
+/// Returns length of output which would be produced by formatting `template` with `args`,
+/// like `snprintf(NULL, 0, ...)` does.
+///
+/// All validation and conversions are done as by [`format_to`], but nothing is written.
+///
+/// ```rust
+/// use formatf::{measure, Value};
+/// let args = [Value::Int(42), Value::String(b"abc")];
+/// assert_eq!(measure(b"%5d|%-4s|", &args).unwrap(), 11);
+/// assert!(measure(b"%d", &[]).is_err());
+/// ```
+///
+/// [`format_to`]: ./fn.format_to.html
+pub fn measure(
+    template: &[u8],
+    args: &[Value],
+) -> Result<usize, FormatToError<core::convert::Infallible>> {
+    measure_with(template, args, &FormatOptions::default())
+}
+
+/// Same as [`measure`], but uses given `options`
+///
+/// [`measure`]: ./fn.measure.html
+pub fn measure_with(
+    template: &[u8],
+    args: &[Value],
+    options: &FormatOptions,
+) -> Result<usize, FormatToError<core::convert::Infallible>> {
+    let mut sink = CountSink(0);
+    format_to_with(template, args, &mut sink, options)?;
+    Ok(sink.0)
 }
 
 /// Formats `template` with given `args`, writing to given `sink`
 ///
+/// Like `printf`, returns number of bytes written. If `sink` fails, number of bytes
+/// written before failure is available in `FormatToError::Sink`.
+///
 /// ```rust,no_run
 /// # fn make_sink() -> &'static mut dyn formatf::BinSink<Err=()> {todo!()}
 /// use formatf::{format_to, Value};
@@ -104,41 +232,306 @@ pub fn format_to<H: BinSink>(
     template: &[u8],
     args: &[Value],
     sink: &mut H,
-) -> Result<(), FormatToError<H::Err>> {
-    let mut fmt = format::Formatter {
-        sink,
-        args,
-        error: None,
-        next_arg: 0,
+) -> Result<usize, FormatToError<H::Err>> {
+    format_to_with(template, args, sink, &FormatOptions::default())
+}
+
+/// Same as [`format_to`], but uses given `options`
+///
+/// [`format_to`]: ./fn.format_to.html
+pub fn format_to_with<H: BinSink>(
+    template: &[u8],
+    args: &[Value],
+    sink: &mut H,
+    options: &FormatOptions,
+) -> Result<usize, FormatToError<H::Err>> {
+    format_to_located(template, args, sink, options).map_err(|(err, _)| err)
+}
+
+/// Same as [`format_to_with`], but also returns location of error
+///
+/// [`format_to_with`]: ./fn.format_to_with.html
+pub fn format_to_located<H: BinSink>(
+    template: &[u8],
+    args: &[Value],
+    sink: &mut H,
+    options: &FormatOptions,
+) -> Result<usize, (FormatToError<H::Err>, ErrorLocation)> {
+    format::run(template, args, sink, options, Default::default())
+}
+
+/// Same as [`format_to_with`], but if formatting fails, everything written to `sink` is
+/// rolled back, so `sink` is left as it was.
+///
+/// This includes sink errors (e.g. when slice is too small) and `FormatToError::ExcessArgs`.
+/// Number of bytes in `FormatToError::Sink` is number of bytes written before error,
+/// which were rolled back.
+///
+/// ```rust
+/// use formatf::{format_to_atomic, CursorSink, FormatOptions, Value};
+/// let mut buf = [0u8; 8];
+/// let mut sink = CursorSink::new(&mut buf);
+/// let options = FormatOptions::default();
+/// format_to_atomic(b"%d;", &[Value::Int(1)], &mut sink, &options).unwrap();
+/// assert!(format_to_atomic(b"%s;", &[Value::Int(2)], &mut sink, &options).is_err());
+/// assert!(format_to_atomic(b"%10d;", &[Value::Int(3)], &mut sink, &options).is_err());
+/// assert_eq!(sink.as_bytes(), b"1;");
+/// ```
+///
+/// [`format_to_with`]: ./fn.format_to_with.html
+pub fn format_to_atomic<S: Rollback>(
+    template: &[u8],
+    args: &[Value],
+    sink: &mut S,
+    options: &FormatOptions,
+) -> Result<usize, FormatToError<S::Err>> {
+    let checkpoint = sink.checkpoint();
+    let res = format_to_with(template, args, sink, options);
+    if res.is_err() {
+        sink.rollback(checkpoint);
+    }
+    res
+}
+
+/// Formats `template`, continuing after errors.
+///
+/// Each erroneous conversion specification is passed to `on_error` together with its
+/// location, and is replaced by `options.placeholder` in output.
+/// Only sink errors and violations of [limits] stop formatting; they are returned and not
+/// passed to `on_error`. On success, returns number of bytes written. If `sink` fails,
+/// number of bytes written before failure is available in `FormatToError::Sink`.
+///
+/// ```rust
+/// use formatf::{format_to_recovering, FormatOptions, SliceSink, Value};
+/// let mut buf = [0u8; 16];
+/// let mut errors = 0;
+/// let options = FormatOptions {
+///     placeholder: Some(b"<?>"),
+///     ..Default::default()
+/// };
+/// let args = [Value::Int(1), Value::Int(2)];
+/// let mut sink = SliceSink(&mut buf);
+/// format_to_recovering(b"%s, %d", &args, &mut sink, &options, |_err, _loc| errors += 1)
+///     .unwrap();
+/// assert_eq!(&buf[..6], b"<?>, 2");
+/// assert_eq!(errors, 1);
+/// ```
+///
+/// [limits]: ./struct.FormatOptions.html#limits
+pub fn format_to_recovering<H: BinSink>(
+    template: &[u8],
+    args: &[Value],
+    sink: &mut H,
+    options: &FormatOptions,
+    mut on_error: impl FnMut(FormatToError<H::Err>, ErrorLocation),
+) -> Result<usize, FormatToError<H::Err>> {
+    let hooks = format::Hooks {
+        on_error: Some(&mut on_error),
+        on_arg: None,
     };
-    visit::visit(template, &mut fmt);
-    match fmt.error.ok_or(()) {
-        Ok(err) => Err(err),
-        Err(ok) => Ok(ok),
+    format::run(template, args, sink, options, hooks).map_err(|(err, _)| err)
+}
+
+/// Result of [`format_recovering`]
+///
+/// [`format_recovering`]: ./fn.format_recovering.html
+#[cfg(feature = "alloc")]
+#[derive(Debug)]
+pub struct FormatReport {
+    /// Formatted output, with placeholders in place of erroneous specifications
+    pub output: Vec<u8>,
+    /// All encountered errors, in order of occurence
+    pub errors: Vec<(FormatToError<core::convert::Infallible>, ErrorLocation)>,
+}
+
+/// Formats `template` to new-allocated buf, continuing after errors.
+///
+/// See [`format_to_recovering`] for details.
+///
+/// [`format_to_recovering`]: ./fn.format_to_recovering.html
+#[cfg(feature = "alloc")]
+pub fn format_recovering(template: &[u8], args: &[Value], options: &FormatOptions) -> FormatReport {
+    let mut buf = VecSink(Vec::new());
+    let mut errors = Vec::new();
+    let mut on_error = |err, loc| errors.push((err, loc));
+    let hooks = format::Hooks {
+        on_error: Some(&mut on_error),
+        on_arg: None,
+    };
+    // `VecSink` never fails, so this is violation of limits
+    if let Err((err, loc)) = format::run(template, args, &mut buf, options, hooks) {
+        errors.push((err, loc));
+    }
+    FormatReport {
+        output: buf.0,
+        errors,
     }
 }
 
 /// This is something like `std::io::Write`, but with `no_std` support.
 ///
-/// In particular, `BinSink` is always implemented for `Vec<u8>` and `&mut [u8]`
+/// In particular, `BinSink` is implemented for `Vec<u8>` (requires `alloc` feature), which
+/// appends data, and for `&mut [u8]`, which writes data to the beginning of slice and
+/// advances it, like `SliceSink` does.
 pub trait BinSink {
     type Err;
     /// Get and process next chunk of data
     fn put(&mut self, data: &[u8]) -> Result<(), Self::Err>;
+
+    /// Process `count` copies of `byte`. Used for padding.
+    ///
+    /// Default implementation calls `put` with chunks from small stack buffer.
+    /// If error is returned, some bytes may have already been processed.
+    fn put_repeat(&mut self, byte: u8, count: usize) -> Result<(), Self::Err> {
+        let chunk = [byte; 64];
+        let mut left = count;
+        while left != 0 {
+            let len = core::cmp::min(left, chunk.len());
+            self.put(&chunk[..len])?;
+            left -= len;
+        }
+        Ok(())
+    }
+
+    /// Process several chunks of data, as if `put` was called for each of them.
+    ///
+    /// Default implementation does exactly that.
+    /// If error is returned, some chunks may have already been processed.
+    fn put_vectored(&mut self, chunks: &[&[u8]]) -> Result<(), Self::Err> {
+        for chunk in chunks {
+            self.put(chunk)?;
+        }
+        Ok(())
+    }
+
+    /// Returns `true` if `put_vectored` is more efficient than several `put` calls.
+    ///
+    /// If so, formatter gathers output of whole message (as long as it fits into small
+    /// stack buffer) and passes it to `put_vectored` at once. Default implementation
+    /// returns `false`.
+    fn is_vectored(&self) -> bool {
+        false
+    }
 }
 
-impl<'a, H: BinSink + ?Sized> BinSink for &'a mut H {
+impl<H: BinSink + ?Sized> BinSink for &mut H {
     type Err = <H as BinSink>::Err;
     #[inline]
     fn put(&mut self, data: &[u8]) -> Result<(), Self::Err> {
         <H as BinSink>::put(&mut **self, data)
     }
+
+    #[inline]
+    fn put_repeat(&mut self, byte: u8, count: usize) -> Result<(), Self::Err> {
+        <H as BinSink>::put_repeat(&mut **self, byte, count)
+    }
+
+    #[inline]
+    fn put_vectored(&mut self, chunks: &[&[u8]]) -> Result<(), Self::Err> {
+        <H as BinSink>::put_vectored(&mut **self, chunks)
+    }
+
+    #[inline]
+    fn is_vectored(&self) -> bool {
+        <H as BinSink>::is_vectored(&**self)
+    }
+}
+
+/// Sink which can discard data written after some point
+pub trait Rollback: BinSink {
+    /// Saved state of sink
+    type Checkpoint;
+    /// Returns current state of sink, which can be restored later
+    fn checkpoint(&self) -> Self::Checkpoint;
+    /// Discards everything written after `checkpoint` was taken
+    fn rollback(&mut self, checkpoint: Self::Checkpoint);
+}
+
+impl<H: Rollback + ?Sized> Rollback for &mut H {
+    type Checkpoint = H::Checkpoint;
+
+    fn checkpoint(&self) -> Self::Checkpoint {
+        (**self).checkpoint()
+    }
+
+    fn rollback(&mut self, checkpoint: Self::Checkpoint) {
+        (**self).rollback(checkpoint)
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl Rollback for Vec<u8> {
+    type Checkpoint = usize;
+
+    fn checkpoint(&self) -> usize {
+        self.len()
+    }
+
+    fn rollback(&mut self, checkpoint: usize) {
+        self.truncate(checkpoint);
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl BinSink for Vec<u8> {
+    type Err = core::convert::Infallible;
+
+    fn put(&mut self, data: &[u8]) -> Result<(), Self::Err> {
+        self.extend_from_slice(data);
+        Ok(())
+    }
+
+    fn put_repeat(&mut self, byte: u8, count: usize) -> Result<(), Self::Err> {
+        self.resize(self.len() + count, byte);
+        Ok(())
+    }
+}
+
+impl BinSink for &mut [u8] {
+    type Err = SliceTooSmall;
+
+    fn put(&mut self, data: &[u8]) -> Result<(), Self::Err> {
+        let mut sink = SliceSink(core::mem::take(self));
+        let res = sink.put(data);
+        *self = sink.0;
+        res
+    }
+
+    fn put_repeat(&mut self, byte: u8, count: usize) -> Result<(), Self::Err> {
+        let mut sink = SliceSink(core::mem::take(self));
+        let res = sink.put_repeat(byte, count);
+        *self = sink.0;
+        res
+    }
 }
 
 /// Helper struct that implements BinSink via delegation to `Write`
 #[cfg(feature = "std")]
 pub struct WriteSink<W>(W);
 
+#[cfg(feature = "std")]
+impl<W: std::io::Write> WriteSink<W> {
+    /// Creates sink which writes to `writer`
+    pub fn new(writer: W) -> Self {
+        WriteSink(writer)
+    }
+
+    /// Returns reference to underlying writer
+    pub fn get_ref(&self) -> &W {
+        &self.0
+    }
+
+    /// Returns mutable reference to underlying writer
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.0
+    }
+
+    /// Returns underlying writer
+    pub fn into_inner(self) -> W {
+        self.0
+    }
+}
+
 #[cfg(feature = "std")]
 impl<W: std::io::Write> BinSink for WriteSink<W> {
     type Err = std::io::Error;
@@ -146,6 +539,39 @@ impl<W: std::io::Write> BinSink for WriteSink<W> {
     fn put(&mut self, data: &[u8]) -> Result<(), Self::Err> {
         self.0.write_all(data)
     }
+
+    fn put_repeat(&mut self, byte: u8, count: usize) -> Result<(), Self::Err> {
+        use std::io::Read;
+        let mut padding = std::io::repeat(byte).take(count as u64);
+        std::io::copy(&mut padding, &mut self.0).map(drop)
+    }
+
+    fn put_vectored(&mut self, chunks: &[&[u8]]) -> Result<(), Self::Err> {
+        use std::io::{ErrorKind, IoSlice};
+        const MAX_SLICES: usize = 16;
+        for group in chunks.chunks(MAX_SLICES) {
+            let mut storage = [IoSlice::new(&[]); MAX_SLICES];
+            for (slice, chunk) in storage.iter_mut().zip(group) {
+                *slice = IoSlice::new(chunk);
+            }
+            let mut slices = &mut storage[..group.len()];
+            // skips leading empty slices
+            IoSlice::advance_slices(&mut slices, 0);
+            while !slices.is_empty() {
+                match self.0.write_vectored(slices) {
+                    Ok(0) => return Err(ErrorKind::WriteZero.into()),
+                    Ok(n) => IoSlice::advance_slices(&mut slices, n),
+                    Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn is_vectored(&self) -> bool {
+        true
+    }
 }
 
 /// Helper struct that implements BinSink via appending bytes to Vec.
@@ -161,6 +587,137 @@ impl BinSink for VecSink {
         self.0.extend_from_slice(data);
         Ok(())
     }
+
+    fn put_repeat(&mut self, byte: u8, count: usize) -> Result<(), Self::Err> {
+        self.0.put_repeat(byte, count)
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl Rollback for VecSink {
+    type Checkpoint = usize;
+
+    fn checkpoint(&self) -> usize {
+        self.0.len()
+    }
+
+    fn rollback(&mut self, checkpoint: usize) {
+        self.0.truncate(checkpoint);
+    }
+}
+
+/// Helper struct that implements BinSink via appending bytes to String.
+/// This Sink fails if data is not valid UTF-8.
+///
+/// Character may be split between several chunks, so [`finish`] should be called after
+/// formatting to check that output does not end with incomplete character.
+///
+/// ```rust
+/// use formatf::{format_to, StringSink, Value};
+/// let mut s = String::from("> ");
+/// let mut sink = StringSink::new(&mut s);
+/// format_to(b"%s", &[Value::String("ñ".as_bytes())], &mut sink).unwrap();
+/// sink.finish().unwrap();
+/// assert_eq!(s, "> ñ");
+/// ```
+///
+/// [`finish`]: #method.finish
+#[cfg(feature = "alloc")]
+pub struct StringSink<'a> {
+    string: &'a mut String,
+    decoder: utf8::Utf8Decoder,
+    /// Number of bytes appended to `string`
+    offset: usize,
+}
+
+/// Error returned by [`StringSink`]
+///
+/// [`StringSink`]: ./struct.StringSink.html
+#[cfg(feature = "alloc")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidUtf8 {
+    /// Position of first invalid byte, counting from beginning of data written to sink
+    pub offset: usize,
+}
+
+#[cfg(feature = "alloc")]
+impl core::fmt::Display for InvalidUtf8 {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "invalid UTF-8 at offset {}", self.offset)
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for InvalidUtf8 {}
+
+#[cfg(feature = "alloc")]
+impl<'a> StringSink<'a> {
+    /// Creates sink which appends to `string`
+    pub fn new(string: &'a mut String) -> Self {
+        StringSink {
+            string,
+            decoder: Default::default(),
+            offset: 0,
+        }
+    }
+
+    /// Checks that data does not end with incomplete character
+    pub fn finish(self) -> Result<(), InvalidUtf8> {
+        if self.decoder.has_pending() {
+            return Err(InvalidUtf8 {
+                offset: self.offset,
+            });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl BinSink for StringSink<'_> {
+    type Err = InvalidUtf8;
+
+    fn put(&mut self, data: &[u8]) -> Result<(), Self::Err> {
+        let (string, offset) = (&mut *self.string, &mut self.offset);
+        self.decoder.decode(data, &mut |piece| match piece {
+            Ok(s) => {
+                string.push_str(s);
+                *offset += s.len();
+                Ok(())
+            }
+            Err(_) => Err(InvalidUtf8 { offset: *offset }),
+        })
+    }
+}
+
+/// Helper struct that implements BinSink via counting bytes and discarding them.
+/// This Sink is infallible.
+#[derive(Debug, Default)]
+pub struct CountSink(pub usize);
+
+impl BinSink for CountSink {
+    type Err = core::convert::Infallible;
+
+    fn put(&mut self, data: &[u8]) -> Result<(), Self::Err> {
+        self.0 += data.len();
+        Ok(())
+    }
+
+    fn put_repeat(&mut self, _byte: u8, count: usize) -> Result<(), Self::Err> {
+        self.0 += count;
+        Ok(())
+    }
+}
+
+impl Rollback for CountSink {
+    type Checkpoint = usize;
+
+    fn checkpoint(&self) -> usize {
+        self.0
+    }
+
+    fn rollback(&mut self, checkpoint: usize) {
+        self.0 = checkpoint;
+    }
 }
 
 /// Helper struct that implements BinSink via appending bytes to slice.
@@ -186,11 +743,99 @@ impl<'a> BinSink for SliceSink<'a> {
         if self.0.len() < data.len() {
             return Err(SliceTooSmall);
         }
-        let (a, b) = core::mem::replace(&mut self.0, &mut []).split_at_mut(data.len());
+        let (a, b) = core::mem::take(&mut self.0).split_at_mut(data.len());
         a.copy_from_slice(data);
         self.0 = b;
         Ok(())
     }
+
+    fn put_repeat(&mut self, byte: u8, count: usize) -> Result<(), Self::Err> {
+        if self.0.len() < count {
+            return Err(SliceTooSmall);
+        }
+        let (a, b) = core::mem::take(&mut self.0).split_at_mut(count);
+        a.fill(byte);
+        self.0 = b;
+        Ok(())
+    }
+}
+
+/// Helper struct that implements BinSink via writing bytes to slice at cursor, which can be
+/// moved back with [`Rollback`].
+/// This Sink only fails when Slice capacity is exceeded
+///
+/// ```rust
+/// use formatf::{format_to, CursorSink, Value};
+/// let mut buf = [0u8; 16];
+/// let mut sink = CursorSink::new(&mut buf);
+/// format_to(b"%s=%d", &[Value::String(b"x"), Value::Int(1)], &mut sink).unwrap();
+/// assert_eq!(sink.as_bytes(), b"x=1");
+/// ```
+///
+/// [`Rollback`]: ./trait.Rollback.html
+pub struct CursorSink<'a> {
+    buf: &'a mut [u8],
+    /// Number of bytes written
+    pos: usize,
+}
+
+impl<'a> CursorSink<'a> {
+    /// Creates sink which writes to the beginning of `buf`
+    pub fn new(buf: &'a mut [u8]) -> Self {
+        CursorSink { buf, pos: 0 }
+    }
+
+    /// Returns number of bytes written
+    pub fn written(&self) -> usize {
+        self.pos
+    }
+
+    /// Returns number of bytes which can still be written
+    pub fn remaining(&self) -> usize {
+        self.buf.len() - self.pos
+    }
+
+    /// Returns written part of buffer
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buf[..self.pos]
+    }
+
+    /// Reserves space for `len` bytes, advancing cursor
+    fn advance(&mut self, len: usize) -> Result<&mut [u8], SliceTooSmall> {
+        if self.remaining() < len {
+            return Err(SliceTooSmall);
+        }
+        let start = self.pos;
+        self.pos += len;
+        Ok(&mut self.buf[start..self.pos])
+    }
+}
+
+impl BinSink for CursorSink<'_> {
+    type Err = SliceTooSmall;
+
+    fn put(&mut self, data: &[u8]) -> Result<(), Self::Err> {
+        self.advance(data.len())?.copy_from_slice(data);
+        Ok(())
+    }
+
+    fn put_repeat(&mut self, byte: u8, count: usize) -> Result<(), Self::Err> {
+        self.advance(count)?.fill(byte);
+        Ok(())
+    }
+}
+
+impl Rollback for CursorSink<'_> {
+    type Checkpoint = usize;
+
+    fn checkpoint(&self) -> usize {
+        self.pos
+    }
+
+    /// Moves cursor back. Bytes after it are not cleared
+    fn rollback(&mut self, checkpoint: usize) {
+        self.pos = core::cmp::min(self.pos, checkpoint);
+    }
 }
 
 /// Allows define `BinSink`s from functions of form FnMut (u8 slice) -> Result<(), E>)
@@ -255,6 +900,667 @@ mod tests {
         }
     }
 
+    #[test]
+    #[cfg(feature = "alloc")]
+    fn strict_mode() {
+        use crate::high::ParseError;
+        let options = FormatOptions {
+            strict: true,
+            ..Default::default()
+        };
+        let check = |fmt: &[u8], args: &[Value]| format_with(fmt, args, &options);
+        let spec_err = |fmt: &[u8], args: &[Value]| match check(fmt, args) {
+            Err(e) => match e.into_error() {
+                FormatToError::Spec(e) => e,
+                other => panic!("unexpected error: {:?}", other),
+            },
+            Ok(buf) => panic!("unexpected success: {:?}", buf),
+        };
+        let int = [Value::Int(5)];
+        let string = [Value::String(b"x")];
+
+        assert_eq!(check(b"%sabc", &string).unwrap(), b"xabc");
+        assert_eq!(check(b"%.d", &int).unwrap(), b"5");
+        assert!(matches!(
+            spec_err(b"%.5.3d", &int),
+            ParseError::RepeatedPrecision
+        ));
+        assert!(matches!(spec_err(b"50%", &[]), ParseError::DanglingPercent));
+        assert!(matches!(
+            spec_err(b"%5", &int),
+            ParseError::UnterminatedSpec
+        ));
+        assert!(matches!(
+            spec_err(b"%-k", &int),
+            ParseError::UnknownSpecifier
+        ));
+        assert!(matches!(spec_err(b"%x", &int), ParseError::Unsupported));
+        assert!(matches!(
+            spec_err(b"%hhs", &string),
+            ParseError::IncompatibleLenModifier
+        ));
+        assert!(matches!(
+            spec_err(b"%#d", &int),
+            ParseError::IncompatibleFlag(b'#')
+        ));
+    }
+
+    #[test]
+    #[cfg(feature = "alloc")]
+    fn unknown_specifier() {
+        use crate::high::ParseError;
+        // unknown byte is taken as specifier, instead of being copied to output after
+        // specification without specifier
+        let err = format(b"a%-yb", &[Value::Int(1)]).unwrap_err();
+        assert!(matches!(
+            err.error(),
+            FormatToError::Spec(ParseError::UnknownSpecifier)
+        ));
+        assert_eq!(err.location().span, Some(1..4));
+        assert_eq!(err.buf(), b"a");
+
+        for fmt in [&b"%5"[..], b"%5%d"] {
+            let err = format(fmt, &[Value::Int(1)]).unwrap_err();
+            assert!(matches!(
+                err.error(),
+                FormatToError::Spec(ParseError::MissingSpecifier)
+            ));
+            assert_eq!(err.location().span, Some(0..2));
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "alloc")]
+    fn nul_bytes() {
+        let args = [Value::String(b"a\0b"), Value::Int(1)];
+        let res = format(b"%s\0%d\0", &args).unwrap();
+        assert_eq!(res, b"a\x00b\x001\x00");
+        let err = format(b"%\0", &args).unwrap_err().into_error();
+        assert!(matches!(
+            err,
+            FormatToError::Spec(crate::high::ParseError::UnknownSpecifier)
+        ));
+
+        let options = FormatOptions {
+            c_strings: true,
+            ..Default::default()
+        };
+        let res = format_with(b"%s:%d\0%d", &args, &options).unwrap();
+        assert_eq!(res, b"a:1");
+    }
+
+    #[test]
+    #[cfg(feature = "alloc")]
+    fn recovery() {
+        let args = [Value::Int(1), Value::String(b"s"), Value::Int(300)];
+        let report = format_recovering(b"%d %d %z %hhd %d|", &args, &FormatOptions::default());
+        assert_eq!(report.output, b"1 %d %z %hhd %d|");
+        let errors: Vec<_> = report
+            .errors
+            .iter()
+            .map(|(err, loc)| (err.description(), loc.clone()))
+            .collect();
+        let loc = |span: core::ops::Range<usize>, arg| ErrorLocation {
+            span: Some(span),
+            arg,
+        };
+        assert_eq!(
+            errors,
+            [
+                (
+                    FormatToError::<()>::BadType.description(),
+                    loc(3..5, Some(1))
+                ),
+                (
+                    FormatToError::<()>::Spec(crate::high::ParseError::UnknownSpecifier)
+                        .description(),
+                    loc(6..9, None)
+                ),
+                (
+                    FormatToError::<()>::NumOverflow.description(),
+                    loc(9..13, Some(2))
+                ),
+                (
+                    FormatToError::<()>::NotEnoughArguments.description(),
+                    loc(14..16, Some(3))
+                ),
+            ]
+        );
+
+        let mut buf = [0u8; 4];
+        let mut errors = 0;
+        let default = FormatOptions::default();
+        let res = format_to_recovering(b"%d %d|", &args, &mut &mut buf[..], &default, |_, _| {
+            errors += 1
+        });
+        assert!(matches!(res, Err(FormatToError::Sink(SliceTooSmall, 4))));
+        assert_eq!((&buf, errors), (b"1 %d", 1));
+        let options = FormatOptions::new().max_specs(Some(1));
+        let res = format_to_recovering(b"%d %d", &args, &mut buf.as_mut(), &options, |_, _| {
+            errors += 1
+        });
+        assert!(matches!(res, Err(FormatToError::TooManySpecs)));
+        assert_eq!(errors, 1);
+    }
+
+    #[test]
+    #[cfg(feature = "alloc")]
+    fn inline_errors() {
+        let options = FormatOptions {
+            inline_errors: true,
+            ..Default::default()
+        };
+        let check = |fmt: &[u8], args: &[Value], expected: &[u8]| {
+            let res = format_with(fmt, args, &options).unwrap();
+            assert_eq!(res, expected);
+        };
+        check(b"%d!", &[Value::String(b"hi")], b"%!d(string=hi)!");
+        check(b"%s %s", &[Value::String(b"a")], b"a %!s(MISSING)");
+        check(
+            b"%d",
+            &[Value::Int(1), Value::Int(3), Value::String(b"x")],
+            b"1%!(EXTRA int=3, string=x)",
+        );
+        check(b"%y%d", &[Value::Int(1), Value::Int(2)], b"%!y(int=1)2");
+        check(b"%hhd", &[Value::Int(300)], b"%!d(int=300)");
+        check(b"%5", &[], b"%!(NOVERB)");
+    }
+
+    #[test]
+    #[cfg(feature = "alloc")]
+    fn excess_args() {
+        let args = [Value::Int(1), Value::Int(2), Value::Int(3)];
+        assert_eq!(format(b"%d", &args).unwrap(), b"1");
+
+        let mut options = FormatOptions {
+            excess_args: ExcessArgsPolicy::Error,
+            ..Default::default()
+        };
+        let err = format_with(b"%d", &args, &options).unwrap_err();
+        assert!(matches!(err.error(), FormatToError::ExcessArgs));
+        assert_eq!(err.buf(), b"1");
+        assert!(format_with(b"%d%d%d", &args, &options).is_ok());
+
+        let report = format_recovering(b"%d", &args, &options);
+        assert_eq!(report.errors.len(), 1);
+        assert_eq!(report.errors[0].1.arg, Some(1));
+
+        let warned = core::cell::Cell::new(0);
+        let warn = |used, total| warned.set(used * 10 + total);
+        options.excess_args = ExcessArgsPolicy::Warn(&warn);
+        assert_eq!(format_with(b"%d", &args, &options).unwrap(), b"1");
+        assert_eq!(warned.get(), 13);
+
+        // dry runs of `measure` and atomic mode do not issue warning
+        let warnings = core::cell::Cell::new(0);
+        let warn = |_, _| warnings.set(warnings.get() + 1);
+        options.excess_args = ExcessArgsPolicy::Warn(&warn);
+        let res = format_with(b"%d", &args, &options).unwrap();
+        assert_eq!((res.as_slice(), res.capacity()), (&b"1"[..], 1));
+        assert_eq!(warnings.get(), 1);
+        let options = options.atomic(true);
+        format_with(b"%d", &args, &options).unwrap();
+        assert_eq!(warnings.get(), 2);
+        // length found by dry runs of limits and atomic mode is reused as capacity
+        let res = format_with(b"%d", &args, &options.max_output(Some(10))).unwrap();
+        assert_eq!((res.as_slice(), res.capacity()), (&b"1"[..], 1));
+        assert_eq!(warnings.get(), 3);
+
+        let usage = args_usage(b"%d %s %d", &args, &FormatOptions::default());
+        assert_eq!(usage.consumed_by, [Some(0..2), Some(3..5), Some(6..8)]);
+        assert!(usage.all_used());
+    }
+
+    #[test]
+    #[cfg(feature = "alloc")]
+    fn overflow_policy() {
+        let check = |policy, fmt: &[u8], x: i128| {
+            let options = FormatOptions {
+                overflow: policy,
+                ..Default::default()
+            };
+            format_with(fmt, &[Value::Int(x)], &options).map_err(|e| e.into_error())
+        };
+        let ok = |policy, fmt: &[u8], x: i128| check(policy, fmt, x).unwrap();
+        assert!(matches!(
+            check(OverflowPolicy::Error, b"%hhd", 300),
+            Err(FormatToError::NumOverflow)
+        ));
+        assert!(matches!(
+            check(OverflowPolicy::Error, b"%u", -1),
+            Err(FormatToError::NumOverflow)
+        ));
+        assert_eq!(ok(OverflowPolicy::Error, b"%u", 4294967295), b"4294967295");
+
+        assert_eq!(ok(OverflowPolicy::Wrap, b"%hhd", 300), b"44");
+        assert_eq!(ok(OverflowPolicy::Wrap, b"%hhd", 200), b"-56");
+        assert_eq!(ok(OverflowPolicy::Wrap, b"%u", -1), b"4294967295");
+        assert_eq!(ok(OverflowPolicy::Wrap, b"%hu", 65537), b"1");
+        assert_eq!(
+            ok(OverflowPolicy::Wrap, b"%ju", -1),
+            u128::MAX.to_string().as_bytes()
+        );
+
+        assert_eq!(ok(OverflowPolicy::Saturate, b"%hhd", 300), b"127");
+        assert_eq!(ok(OverflowPolicy::Saturate, b"%hhd", -300), b"-128");
+        assert_eq!(ok(OverflowPolicy::Saturate, b"%u", -1), b"0");
+        assert_eq!(ok(OverflowPolicy::Saturate, b"%hhu", 300), b"255");
+    }
+
+    #[test]
+    #[cfg(feature = "alloc")]
+    fn coercion() {
+        let options = FormatOptions {
+            coerce_args: true,
+            ..Default::default()
+        };
+        let check = |fmt: &[u8], arg: &[u8], expected: &[u8]| {
+            let res = format_with(fmt, &[Value::String(arg)], &options).unwrap();
+            assert_eq!(res, expected);
+        };
+        check(b"%d", b"42", b"42");
+        check(b"%d", b" -42", b"-42");
+        check(b"%d", b"0x1F", b"31");
+        check(b"%d", b"-0X1f", b"-31");
+        check(b"%d", b"017", b"15");
+        check(b"%d", b"0", b"0");
+        check(b"%d", b"'A", b"65");
+        check(b"%d", b"\"\xd0\xb0", b"1072");
+        check(b"%d", b"", b"0");
+        check(b"%5s", b"x", b"    x");
+        let res = format_with(b"%4s|%.1s", &[Value::Int(42), Value::Int(-7)], &options).unwrap();
+        assert_eq!(res, b"  42|-");
+
+        let report = format_recovering(
+            b"%d %d %d",
+            &[
+                Value::String(b"12abc"),
+                Value::String(b"5"),
+                Value::String(b"--1"),
+            ],
+            &options,
+        );
+        assert_eq!(report.output, b"%d 5 %d");
+        let failed: Vec<_> = report
+            .errors
+            .iter()
+            .map(|(err, loc)| {
+                assert!(matches!(err, FormatToError::Coercion(_)));
+                loc.arg
+            })
+            .collect();
+        assert_eq!(failed, [Some(0), Some(2)]);
+    }
+
+    #[test]
+    #[cfg(feature = "alloc")]
+    fn options() {
+        use crate::high::ParseError;
+        let run = |fmt: &[u8], args: &[Value], options: &FormatOptions| {
+            format_with(fmt, args, options).map_err(|e| e.into_error())
+        };
+        let int = [Value::Int(1234567)];
+
+        let options = FormatOptions::new().max_width(Some(10));
+        assert_eq!(run(b"%10d", &int, &options).unwrap(), b"   1234567");
+        assert!(matches!(
+            run(b"%11d", &int, &options),
+            Err(FormatToError::LimitExceeded)
+        ));
+        assert!(matches!(
+            run(b"%.11s", &[Value::String(b"")], &options),
+            Err(FormatToError::LimitExceeded)
+        ));
+
+        let options = FormatOptions::new().allowed_specifiers(Some(b"s"));
+        assert!(matches!(
+            run(b"%d", &int, &options),
+            Err(FormatToError::Forbidden)
+        ));
+        // allowlist does not have to be static
+        let allowed = b"d".to_vec();
+        let options = FormatOptions::new().allowed_specifiers(Some(&allowed));
+        assert_eq!(run(b"%d", &int, &options).unwrap(), b"1234567");
+
+        assert!(matches!(
+            run(b"%'d", &int, &FormatOptions::new()),
+            Err(FormatToError::Unsupported)
+        ));
+        let options = FormatOptions::new().locale(Some(Locale::C));
+        assert_eq!(run(b"%'d", &int, &options).unwrap(), b"1234567");
+        let options = FormatOptions::new().locale(Some(Locale::with_separator(',')));
+        assert_eq!(run(b"%'d", &int, &options).unwrap(), b"1,234,567");
+        assert_eq!(run(b"%d", &int, &options).unwrap(), b"1234567");
+        assert_eq!(
+            run(b"%'12d", &[Value::Int(-123456)], &options).unwrap(),
+            b"    -123,456"
+        );
+        assert_eq!(run(b"%'d", &[Value::Int(999)], &options).unwrap(), b"999");
+        let options = FormatOptions::new().locale(Some(Locale::with_separator('\u{202f}')));
+        assert_eq!(
+            run(b"%'d", &[Value::Int(1000)], &options).unwrap(),
+            "1\u{202f}000".as_bytes()
+        );
+
+        let strict = FormatOptions::new().strict(true);
+        assert!(matches!(
+            run(b"%qd", &int, &strict),
+            Err(FormatToError::Spec(ParseError::UnknownLenModifier))
+        ));
+        let gnu = strict.clone().dialect(Dialect::Gnu);
+        assert_eq!(run(b"%qd", &int, &gnu).unwrap(), b"1234567");
+        let c99 = strict.dialect(Dialect::C99);
+        assert!(matches!(
+            run(b"%'d", &int, &c99),
+            Err(FormatToError::Spec(ParseError::UnknownFlag(b'\'')))
+        ));
+    }
+
+    #[test]
+    #[cfg(feature = "alloc")]
+    fn limits() {
+        let check = |fmt: &[u8], args: &[Value]| {
+            let mut buf = [0u8; 256];
+            let mut sink = SliceSink(&mut buf);
+            let res = format_to_located(fmt, args, &mut sink, &FormatOptions::untrusted());
+            let untouched = sink.0.len() == 256;
+            res.map(|_| untouched).map_err(|(err, loc)| {
+                // limits are checked before anything is written
+                assert!(untouched);
+                (err, loc)
+            })
+        };
+        let int = [Value::Int(1)];
+        assert!(check(b"a%-3d", &int).is_ok());
+        assert!(matches!(
+            check(b"abc%999999999d", &int),
+            Err((FormatToError::LimitExceeded, _))
+        ));
+        assert!(matches!(
+            check(b"abc%.999999999999999999999999s", &int),
+            Err((FormatToError::LimitExceeded, _))
+        ));
+        assert!(matches!(
+            check(b"abc%n", &int),
+            Err((FormatToError::Forbidden, _))
+        ));
+        assert!(matches!(
+            check(b"%#d", &int),
+            Err((FormatToError::Forbidden, _))
+        ));
+        let many = [b"%%".as_ref(); 65].concat();
+        assert!(check(&many.repeat(2), &[]).is_ok());
+        let many = [b"%d".as_ref(); 65].concat();
+        let err = check(&many, &[]).unwrap_err();
+        assert!(matches!(err.0, FormatToError::TooManySpecs));
+        assert_eq!(err.1.span, Some(128..130));
+
+        let options = FormatOptions::new().max_output(Some(5));
+        let mut buf = Vec::new();
+        let res = format_to_located(b"a%4d", &int, &mut VecSink(Vec::new()), &options);
+        assert!(res.is_ok());
+        let res = format_to_located(
+            b"abc%4d",
+            &int,
+            &mut CallbackSink::from(|data: &[u8]| {
+                buf.extend_from_slice(data);
+                Ok::<_, ()>(())
+            }),
+            &options,
+        );
+        let (err, loc) = res.unwrap_err();
+        assert!(matches!(err, FormatToError::OutputTooLarge));
+        assert_eq!(loc.span, Some(3..6));
+        assert!(buf.is_empty());
+
+        // dry run does not issue warning
+        let warnings = core::cell::Cell::new(0);
+        let warn = |_, _| warnings.set(warnings.get() + 1);
+        let warn_options = options.clone().excess_args(ExcessArgsPolicy::Warn(&warn));
+        let args = [Value::Int(1), Value::Int(2)];
+        format_to_with(b"%d", &args, &mut VecSink(Vec::new()), &warn_options).unwrap();
+        assert_eq!(warnings.get(), 1);
+
+        let report = format_recovering(b"ab%5s", &[Value::String(b"x")], &options);
+        assert!(report.output.is_empty());
+        assert!(matches!(report.errors[0].0, FormatToError::OutputTooLarge));
+    }
+
+    #[test]
+    #[cfg(feature = "alloc")]
+    fn atomic() {
+        let args = [Value::Int(1), Value::Int(2)];
+        let err = format_with(b"%d %s", &args, &FormatOptions::default()).unwrap_err();
+        assert_eq!(err.buf(), b"1 ");
+        let options = FormatOptions::new().atomic(true);
+        let err = format_with(b"%d %s", &args, &options).unwrap_err();
+        assert!(matches!(err.error(), FormatToError::BadType));
+        assert_eq!(err.location().arg, Some(1));
+        assert!(err.buf().is_empty());
+        let excess = options.clone().excess_args(ExcessArgsPolicy::Error);
+        assert!(format_with(b"%d", &args, &excess)
+            .unwrap_err()
+            .buf()
+            .is_empty());
+        assert_eq!(format_with(b"%d %d", &args, &options).unwrap(), b"1 2");
+
+        let mut sink = VecSink(b"log: ".to_vec());
+        let res = format_to_atomic(b"%d;%d", &args[..1], &mut sink, &FormatOptions::default());
+        assert!(matches!(res, Err(FormatToError::NotEnoughArguments)));
+        format_to_atomic(b"%d;%d", &args, &mut sink, &excess).unwrap();
+        assert_eq!(sink.0, b"log: 1;2");
+
+        let mut buf = *b"........";
+        let mut sink = CursorSink::new(&mut buf);
+        format_to_atomic(b"%d,", &args, &mut sink, &options).unwrap();
+        let res = format_to_atomic(b"%d,%5d", &args, &mut sink, &options);
+        assert!(matches!(res, Err(FormatToError::Sink(SliceTooSmall, 6))));
+        assert_eq!(sink.written(), 2);
+        format_to_atomic(b"%d", &args[1..], &mut sink, &options).unwrap();
+        // rolled back bytes are not cleared
+        assert_eq!(&buf, b"1,2,    ");
+    }
+
+    #[test]
+    fn written_count() {
+        let mut buf = [0u8; 8];
+        let args = [Value::String(b"abc"), Value::Int(-5)];
+        let res = format_to(b"%s=%3d", &args, &mut SliceSink(&mut buf));
+        assert_eq!(res.unwrap(), 7);
+        assert_eq!(&buf[..7], b"abc= -5");
+
+        let mut buf = [0u8; 5];
+        let res = format_to(b"%s=%3d", &args, &mut SliceSink(&mut buf));
+        assert!(matches!(res, Err(FormatToError::Sink(SliceTooSmall, 5))));
+        assert_eq!(measure(b"%s=%3d", &args).unwrap(), 7);
+    }
+
+    #[test]
+    fn truncation() {
+        let args = [Value::String("añb".as_bytes())];
+        let check = |size: usize, utf8: bool, expected: &[u8]| {
+            let mut buf = [0xffu8; 8];
+            let mut sink = TruncatingSink::new(&mut buf[..size]).utf8_boundaries(utf8);
+            format_to(b"%s", &args, &mut sink).unwrap();
+            assert_eq!(sink.as_bytes(), expected);
+            assert_eq!(sink.required(), 4);
+            assert_eq!(sink.is_truncated(), expected.len() < 4);
+        };
+        check(2, false, b"a\xc3");
+        check(2, true, b"a");
+        check(3, true, "añ".as_bytes());
+        check(4, true, "añb".as_bytes());
+
+        // character split between chunks
+        let mut buf = [0xffu8; 4];
+        let mut sink = TruncatingSink::new(&mut buf)
+            .nul_terminated(true)
+            .utf8_boundaries(true);
+        sink.put(b"a\xe2\x82").unwrap();
+        sink.put(b"\xacb").unwrap();
+        assert_eq!((sink.as_bytes(), sink.required()), (&b"a"[..], 5));
+        assert_eq!(&buf, b"a\0\x82\0");
+
+        let mut buf = [0xffu8; 3];
+        let res = snprintf(&mut buf, b"ab%s", &args).unwrap();
+        assert_eq!(
+            (res.written, res.required, res.is_truncated()),
+            (2, 6, true)
+        );
+        assert_eq!(&buf, b"ab\0");
+        let res = snprintf(&mut [], b"ab", &[]).unwrap();
+        assert_eq!((res.written, res.required), (0, 2));
+    }
+
+    #[test]
+    #[cfg(feature = "alloc")]
+    fn builtin_sinks() {
+        let args = [Value::String(b"ab"), Value::String("ñ".as_bytes())];
+
+        let mut buf = b">".to_vec();
+        format_to(b"%s", &args, &mut buf).unwrap();
+        assert_eq!(buf, b">ab");
+
+        let mut arr = [0u8; 4];
+        let mut slice = &mut arr[..];
+        format_to(b"%s", &args, &mut slice).unwrap();
+        assert_eq!(slice.len(), 2);
+        let res = format_to(b"%s%s", &args, &mut slice);
+        assert!(matches!(res, Err(FormatToError::Sink(SliceTooSmall, 2))));
+        assert_eq!(&arr, b"abab");
+
+        let mut s = String::new();
+        let mut sink = StringSink::new(&mut s);
+        format_to(b"%s%.1s", &args, &mut sink).unwrap();
+        assert_eq!(sink.finish(), Err(InvalidUtf8 { offset: 2 }));
+        let mut sink = StringSink::new(&mut s);
+        format_to(b"%.1s", &args[1..], &mut sink).unwrap();
+        let res = format_to(b"|%s", &args[1..], &mut sink);
+        assert!(matches!(
+            res,
+            Err(FormatToError::Sink(InvalidUtf8 { offset: 0 }, 0))
+        ));
+        assert_eq!(s, "ab");
+
+        #[cfg(feature = "std")]
+        {
+            let mut sink = WriteSink::new(Vec::new());
+            format_to(b"%s", &args, &mut sink).unwrap();
+            assert_eq!(sink.into_inner(), b"ab");
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "alloc")]
+    fn fmt_write() {
+        let check = |fmt: &[u8], args: &[Value], non_utf8, expected: &str| {
+            let mut s = String::new();
+            let mut sink = FmtWriteSink::new(&mut s).non_utf8(non_utf8);
+            let res = format_to(fmt, args, &mut sink).map(|_| ());
+            let res = res
+                .map_err(|_| core::fmt::Error)
+                .and_then(|()| sink.finish());
+            assert_eq!(res.map(|()| s.as_str()), Ok(expected));
+        };
+        let args = [Value::String(b"\xc3"), Value::String(b"\xb1\xe2\x82")];
+        check(b"%s%s", &args, NonUtf8::Replace, "\u{f1}\u{fffd}");
+        check(
+            b"%s|%s",
+            &args,
+            NonUtf8::Replace,
+            "\u{fffd}|\u{fffd}\u{fffd}",
+        );
+        check(b"%s%s", &args, NonUtf8::Escape, "\u{f1}\\xe2\\x82");
+        let mut sink = FmtWriteSink::new(String::new()).non_utf8(NonUtf8::Error);
+        assert!(format_to(b"%s%s", &args, &mut sink).is_ok());
+        assert!(sink.finish().is_err());
+
+        let args = [Value::String(b"x\xff"), Value::Int(5)];
+        let msg = Formatted::new(b"%s=%03d", &args);
+        assert_eq!(msg.to_string(), "x\u{fffd}=005");
+    }
+
+    #[test]
+    fn bulk_padding() {
+        let mut calls = 0;
+        let mut len = 0;
+        let mut sink = CallbackSink::from(|data: &[u8]| {
+            assert!(data.iter().all(|&c| c == b' ') || data == b"42");
+            calls += 1;
+            len += data.len();
+            Ok::<_, ()>(())
+        });
+        assert_eq!(
+            format_to(b"%200d", &[Value::Int(42)], &mut sink).unwrap(),
+            200
+        );
+        assert_eq!((calls, len), (5, 200));
+
+        let mut buf = [0u8; 10];
+        let mut sink = SliceSink(&mut buf);
+        format_to(b"%-4d|%03d", &[Value::Int(1), Value::Int(2)], &mut sink).unwrap();
+        assert_eq!(sink.0.len(), 2);
+        assert_eq!(&buf[..8], b"1   |002");
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn vectored_output() {
+        use std::io::{IoSlice, Write};
+
+        /// Writer which accepts at most `limit` bytes per call
+        struct Writer {
+            data: Vec<u8>,
+            calls: usize,
+            limit: usize,
+        }
+
+        impl Write for Writer {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.write_vectored(&[IoSlice::new(buf)])
+            }
+
+            fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> std::io::Result<usize> {
+                self.calls += 1;
+                let mut written = 0;
+                for buf in bufs {
+                    let len = core::cmp::min(buf.len(), self.limit - written);
+                    self.data.extend_from_slice(&buf[..len]);
+                    written += len;
+                }
+                Ok(written)
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let args = [Value::String(b"key"), Value::Int(42), Value::Int(7)];
+        let fmt = b"%s=%5d|%-3d%%|%300s";
+        let long_args = [
+            Value::String(b"k"),
+            Value::Int(1),
+            Value::Int(2),
+            Value::String(b"x"),
+        ];
+        for &limit in &[usize::MAX, 5] {
+            let mut sink = WriteSink::new(Writer {
+                data: Vec::new(),
+                calls: 0,
+                limit,
+            });
+            assert_eq!(format_to(&fmt[..14], &args, &mut sink).unwrap(), 15);
+            let writer = sink.get_ref();
+            assert_eq!(writer.data, b"key=   42|7  %|");
+            assert_eq!(writer.calls, if limit == 5 { 3 } else { 1 });
+
+            sink.get_mut().data.clear();
+            format_to(fmt, &long_args, &mut sink).unwrap();
+            assert_eq!(sink.get_ref().data, format(fmt, &long_args).unwrap());
+        }
+    }
+
     #[test]
     #[cfg(feature = "alloc")]
     fn padding_simple_int() {
@@ -0,0 +1,128 @@
+//! Checking of limits set in `FormatOptions`
+
+use crate::{
+    format::{self, Hooks},
+    visit::{self, ConversionSpecification, FormatStringVisitor},
+    BinSink, ErrorLocation, ExcessArgsPolicy, FormatOptions, FormatToError, Value,
+};
+
+/// Checks that formatting `template` with `args` does not violate limits from `options`.
+///
+/// If `max_output` is set, returns length of output found by dry run. It is not less than
+/// actual length.
+///
+/// `template` must already be truncated in C-string mode.
+pub(crate) fn check<E>(
+    template: &[u8],
+    args: &[Value],
+    options: &FormatOptions,
+) -> Result<Option<usize>, (FormatToError<E>, ErrorLocation)> {
+    let mut checker = SpecChecker {
+        options,
+        specs: 0,
+        error: None,
+    };
+    visit::visit_with(template, &options.parse_config(), &mut checker);
+    if let Some(err) = checker.error {
+        return Err(err);
+    }
+
+    let max_output = match options.max_output {
+        Some(max_output) => max_output,
+        None => return Ok(None),
+    };
+    // Dry run. It continues after errors, so counted size is never less than actual one.
+    // Excess arguments do not affect output length, and warning is issued by actual run
+    let dry_options = FormatOptions {
+        excess_args: ExcessArgsPolicy::Ignore,
+        ..options.clone()
+    };
+    let mut sink = LimitedCounter {
+        count: 0,
+        limit: max_output,
+    };
+    let mut on_error = |_, _| {};
+    let hooks = Hooks {
+        on_error: Some(&mut on_error),
+        on_arg: None,
+    };
+    match format::run_unchecked(template, args, &mut sink, &dry_options, hooks) {
+        Ok(len) => Ok(Some(len)),
+        Err((_, loc)) => Err((FormatToError::OutputTooLarge, loc)),
+    }
+}
+
+/// Parses decimal number, saturating on overflow
+pub(crate) fn parse_decimal(digits: &[u8]) -> usize {
+    digits
+        .iter()
+        .take_while(|c| c.is_ascii_digit())
+        .fold(0usize, |acc, &c| {
+            acc.saturating_mul(10).saturating_add(usize::from(c - b'0'))
+        })
+}
+
+/// Checks limits which only depend on format string
+struct SpecChecker<'a, E> {
+    options: &'a FormatOptions<'a>,
+    specs: usize,
+    error: Option<(FormatToError<E>, ErrorLocation)>,
+}
+
+impl<E> SpecChecker<'_, E> {
+    fn check(&mut self, spec: &ConversionSpecification) -> Result<(), FormatToError<E>> {
+        let options = self.options;
+        self.specs += 1;
+        if options.max_specs.is_some_and(|max| self.specs > max) {
+            return Err(FormatToError::TooManySpecs);
+        }
+        if !options.is_allowed(spec) {
+            return Err(FormatToError::Forbidden);
+        }
+        if let Some(max_width) = options.max_width {
+            let width = parse_decimal(spec.field_width);
+            let prec = parse_decimal(spec.precision.get(1..).unwrap_or_default());
+            if width > max_width || prec > max_width {
+                return Err(FormatToError::LimitExceeded);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<E> FormatStringVisitor for SpecChecker<'_, E> {
+    fn visit_specification(&mut self, spec: ConversionSpecification) {
+        if self.error.is_some() {
+            return;
+        }
+        if let Err(err) = self.check(&spec) {
+            let loc = ErrorLocation {
+                span: Some(spec.span),
+                arg: None,
+            };
+            self.error = Some((err, loc));
+        }
+    }
+}
+
+/// Sink which counts bytes and fails when `limit` is exceeded
+struct LimitedCounter {
+    count: usize,
+    limit: usize,
+}
+
+impl BinSink for LimitedCounter {
+    type Err = ();
+
+    fn put(&mut self, data: &[u8]) -> Result<(), Self::Err> {
+        self.put_repeat(0, data.len())
+    }
+
+    fn put_repeat(&mut self, _byte: u8, count: usize) -> Result<(), Self::Err> {
+        self.count = self.count.saturating_add(count);
+        if self.count > self.limit {
+            return Err(());
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,362 @@
+//! Formatting settings
+
+use crate::{
+    high::{Dialect, ParseError, ParsedConversionSpecification},
+    visit::{ConversionSpecification, ParseConfig},
+};
+
+/// Settings which control behavior of [`format_with`] and [`format_to_with`].
+///
+/// `FormatOptions::default()` gives behavior of [`format`] and [`format_to`].
+/// Options can be set either directly or using builder methods:
+/// ```rust
+/// use formatf::{format_to_with, FormatOptions, OverflowPolicy, SliceSink, Value};
+/// let options = FormatOptions::new()
+///     .strict(true)
+///     .overflow(OverflowPolicy::Wrap)
+///     .max_width(Some(80));
+/// let mut buf = [0u8; 2];
+/// format_to_with(b"%hhd", &[Value::Int(300)], &mut SliceSink(&mut buf), &options).unwrap();
+/// assert_eq!(&buf, b"44");
+/// ```
+///
+/// # Limits
+/// Some options restrict what format string can do, so that it is safe to format templates
+/// from untrusted sources. These are `max_width`, `allowed_specifiers`, `allowed_flags`,
+/// `max_output` and `max_specs`; [`FormatOptions::untrusted`] provides reasonable defaults.
+///
+/// Limits are checked before any output is produced, so if they are violated,
+/// sink is left untouched. Limit violations are not recovered from and are not rendered
+/// inline: in recovery mode, error is returned instead of being passed to handler, and no
+/// output is produced.
+///
+/// [`FormatOptions::untrusted`]: #method.untrusted
+///
+/// [`format`]: ./fn.format.html
+/// [`format_to`]: ./fn.format_to.html
+/// [`format_with`]: ./fn.format_with.html
+/// [`format_to_with`]: ./fn.format_to_with.html
+#[derive(Debug, Clone, Default)]
+pub struct FormatOptions<'a> {
+    /// Validate format string against C99/POSIX grammar.
+    ///
+    /// Malformed specifications (such as `%.5.3d`, `%hhs` or trailing `%`) are reported as
+    /// [`FormatToError::Spec`] with dedicated [`ParseError`] variants instead of
+    /// being parsed loosely.
+    ///
+    /// [`FormatToError::Spec`]: ./enum.FormatToError.html#variant.Spec
+    /// [`ParseError`]: ./high/enum.ParseError.html
+    pub strict: bool,
+    /// Treat format string and `%s` arguments as C strings.
+    ///
+    /// Format string is processed only up to first NUL byte, and string arguments are
+    /// truncated at their first NUL byte, like `printf` does.
+    /// By default NUL is ordinary byte, which is copied to output as is.
+    pub c_strings: bool,
+    /// Written instead of erroneous conversion specification by [`format_to_recovering`].
+    ///
+    /// If `None`, specification is copied to output as is.
+    ///
+    /// [`format_to_recovering`]: ./fn.format_to_recovering.html
+    pub placeholder: Option<&'a [u8]>,
+    /// Instead of failing, write description of error to output, like Go's `fmt` does.
+    ///
+    /// For example, type mismatch is rendered as `%!d(string=hi)`, missing argument as
+    /// `%!d(MISSING)`, and unused arguments as `%!(EXTRA int=3)` at the end of output.
+    /// As in Go, specification with unknown conversion specifier consumes an argument.
+    ///
+    /// With this option, formatting only fails if sink fails, so [`format_with`] never fails.
+    /// When combined with recovery mode, errors are still reported to handler, but their
+    /// descriptions are written instead of placeholder.
+    ///
+    /// [`format_with`]: ./fn.format_with.html
+    pub inline_errors: bool,
+    /// Validate template and arguments before writing anything.
+    ///
+    /// Formatting is done twice: first without output, and then to sink only if the first
+    /// pass succeeded. So errors other than sink errors leave sink untouched, and
+    /// [`FormatFnError::buf`] is empty. Has no effect in recovery mode and with
+    /// `inline_errors`, where formatting does not stop on errors.
+    ///
+    /// To discard output on sink errors as well, see [`format_to_atomic`].
+    ///
+    /// [`FormatFnError::buf`]: ./struct.FormatFnError.html#method.buf
+    /// [`format_to_atomic`]: ./fn.format_to_atomic.html
+    pub atomic: bool,
+    /// What to do if format string does not use all arguments
+    pub excess_args: ExcessArgsPolicy<'a>,
+    /// What to do if integer argument does not fit into type requested by
+    /// conversion specification
+    pub overflow: OverflowPolicy,
+    /// Convert arguments between strings and numbers, like `printf(1)` does.
+    ///
+    /// String argument of numeric conversion is parsed as decimal, hexadecimal (`0x1F`) or
+    /// octal (`017`) number, or as character code (`'A`); failure is reported as
+    /// [`FormatToError::Coercion`]. Integer argument of `%s` is printed in decimal.
+    ///
+    /// [`FormatToError::Coercion`]: ./enum.FormatToError.html#variant.Coercion
+    pub coerce_args: bool,
+    /// Maximal allowed field width and precision.
+    ///
+    /// Larger values are reported as [`FormatToError::LimitExceeded`].
+    /// This is one of [limits](#limits).
+    ///
+    /// [`FormatToError::LimitExceeded`]: ./enum.FormatToError.html#variant.LimitExceeded
+    pub max_width: Option<usize>,
+    /// Conversion specifiers (such as `b"ds"`) which can be used in format string.
+    ///
+    /// Other conversions are reported as [`FormatToError::Forbidden`].
+    /// If `None`, all supported conversions are allowed.
+    /// This is one of [limits](#limits).
+    ///
+    /// [`FormatToError::Forbidden`]: ./enum.FormatToError.html#variant.Forbidden
+    pub allowed_specifiers: Option<&'a [u8]>,
+    /// Flags (such as `b"-0"`) which can be used in format string.
+    ///
+    /// Other flags are reported as [`FormatToError::Forbidden`].
+    /// If `None`, all supported flags are allowed.
+    /// This is one of [limits](#limits).
+    ///
+    /// [`FormatToError::Forbidden`]: ./enum.FormatToError.html#variant.Forbidden
+    pub allowed_flags: Option<&'a [u8]>,
+    /// Maximal number of bytes in output.
+    ///
+    /// If output would be larger, [`FormatToError::OutputTooLarge`] is returned.
+    /// This is one of [limits](#limits).
+    ///
+    /// [`FormatToError::OutputTooLarge`]: ./enum.FormatToError.html#variant.OutputTooLarge
+    pub max_output: Option<usize>,
+    /// Maximal number of conversion specifications in format string.
+    ///
+    /// If there are more, [`FormatToError::TooManySpecs`] is returned.
+    /// This is one of [limits](#limits).
+    ///
+    /// [`FormatToError::TooManySpecs`]: ./enum.FormatToError.html#variant.TooManySpecs
+    pub max_specs: Option<usize>,
+    /// Locale used for thousands grouping (`'` flag).
+    ///
+    /// If `None`, `'` flag is reported as unsupported.
+    pub locale: Option<Locale>,
+    /// Grammar used in strict mode
+    pub dialect: Dialect,
+}
+
+impl<'a> FormatOptions<'a> {
+    /// Returns default options, same as `FormatOptions::default()`
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns options suitable for formatting templates from untrusted sources.
+    ///
+    /// Only `%d`, `%i`, `%u` and `%s` with flags `-`, `0`, ` `, `+` are allowed, field width
+    /// and precision are limited by 256, number of specifications by 64, and output size
+    /// by 64 KiB.
+    pub fn untrusted() -> Self {
+        FormatOptions {
+            max_width: Some(256),
+            allowed_specifiers: Some(b"dius"),
+            allowed_flags: Some(b"-0 +"),
+            max_output: Some(64 * 1024),
+            max_specs: Some(64),
+            ..Self::default()
+        }
+    }
+
+    /// Sets `strict`
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Sets `c_strings`
+    pub fn c_strings(mut self, c_strings: bool) -> Self {
+        self.c_strings = c_strings;
+        self
+    }
+
+    /// Sets `placeholder`
+    pub fn placeholder(mut self, placeholder: Option<&'a [u8]>) -> Self {
+        self.placeholder = placeholder;
+        self
+    }
+
+    /// Sets `inline_errors`
+    pub fn inline_errors(mut self, inline_errors: bool) -> Self {
+        self.inline_errors = inline_errors;
+        self
+    }
+
+    /// Sets `atomic`
+    pub fn atomic(mut self, atomic: bool) -> Self {
+        self.atomic = atomic;
+        self
+    }
+
+    /// Sets `excess_args`
+    pub fn excess_args(mut self, excess_args: ExcessArgsPolicy<'a>) -> Self {
+        self.excess_args = excess_args;
+        self
+    }
+
+    /// Sets `overflow`
+    pub fn overflow(mut self, overflow: OverflowPolicy) -> Self {
+        self.overflow = overflow;
+        self
+    }
+
+    /// Sets `coerce_args`
+    pub fn coerce_args(mut self, coerce_args: bool) -> Self {
+        self.coerce_args = coerce_args;
+        self
+    }
+
+    /// Sets `max_width`
+    pub fn max_width(mut self, max_width: Option<usize>) -> Self {
+        self.max_width = max_width;
+        self
+    }
+
+    /// Sets `allowed_specifiers`
+    pub fn allowed_specifiers(mut self, allowed_specifiers: Option<&'a [u8]>) -> Self {
+        self.allowed_specifiers = allowed_specifiers;
+        self
+    }
+
+    /// Sets `allowed_flags`
+    pub fn allowed_flags(mut self, allowed_flags: Option<&'a [u8]>) -> Self {
+        self.allowed_flags = allowed_flags;
+        self
+    }
+
+    /// Sets `max_output`
+    pub fn max_output(mut self, max_output: Option<usize>) -> Self {
+        self.max_output = max_output;
+        self
+    }
+
+    /// Sets `max_specs`
+    pub fn max_specs(mut self, max_specs: Option<usize>) -> Self {
+        self.max_specs = max_specs;
+        self
+    }
+
+    /// Sets `locale`
+    pub fn locale(mut self, locale: Option<Locale>) -> Self {
+        self.locale = locale;
+        self
+    }
+
+    /// Sets `dialect`
+    pub fn dialect(mut self, dialect: Dialect) -> Self {
+        self.dialect = dialect;
+        self
+    }
+
+    /// Checks whether any of [limits](#limits) is set
+    pub(crate) fn has_limits(&self) -> bool {
+        self.max_width.is_some()
+            || self.allowed_specifiers.is_some()
+            || self.allowed_flags.is_some()
+            || self.max_output.is_some()
+            || self.max_specs.is_some()
+    }
+
+    pub(crate) fn parse_config(&self) -> ParseConfig {
+        ParseConfig {
+            strict: self.strict,
+        }
+    }
+
+    /// Checks `spec` against `allowed_specifiers` and `allowed_flags`
+    pub(crate) fn is_allowed(&self, spec: &ConversionSpecification) -> bool {
+        let all_allowed = |used: &[u8], allowed: Option<&[u8]>| {
+            allowed.map_or(true, |allowed| used.iter().all(|c| allowed.contains(c)))
+        };
+        all_allowed(spec.specifier, self.allowed_specifiers)
+            && all_allowed(spec.flags, self.allowed_flags)
+    }
+
+    /// Parses `spec`, checking it against `dialect` in strict mode
+    pub(crate) fn parse_spec(
+        &self,
+        spec: ConversionSpecification,
+    ) -> Result<ParsedConversionSpecification, ParseError> {
+        if self.strict {
+            ParsedConversionSpecification::try_parse_dialect(spec, self.dialect)
+        } else {
+            ParsedConversionSpecification::try_parse(spec)
+        }
+    }
+}
+
+/// Locale-specific formatting settings
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Locale {
+    /// Separator inserted between digit groups by `'` flag.
+    /// `None` means that digits are not grouped
+    pub thousands_sep: Option<char>,
+    /// Number of digits in group
+    pub group_size: u8,
+}
+
+impl Locale {
+    /// `C` (`POSIX`) locale, which does not group digits
+    pub const C: Locale = Locale {
+        thousands_sep: None,
+        group_size: 3,
+    };
+
+    /// Locale which groups digits by three using `sep`, e.g. `1,234,567`
+    pub fn with_separator(sep: char) -> Locale {
+        Locale {
+            thousands_sep: Some(sep),
+            group_size: 3,
+        }
+    }
+}
+
+/// Specifies how arguments which were not used by format string are treated
+#[derive(Clone, Copy, Default)]
+pub enum ExcessArgsPolicy<'a> {
+    /// Do nothing, like `printf`. This is default.
+    #[default]
+    Ignore,
+    /// Return [`FormatToError::ExcessArgs`] after output is produced.
+    ///
+    /// In recovery mode, error is passed to handler instead.
+    ///
+    /// [`FormatToError::ExcessArgs`]: ./enum.FormatToError.html#variant.ExcessArgs
+    Error,
+    /// Call given function with number of used arguments and total number of arguments
+    Warn(&'a dyn Fn(usize, usize)),
+}
+
+impl core::fmt::Debug for ExcessArgsPolicy<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            ExcessArgsPolicy::Ignore => f.write_str("Ignore"),
+            ExcessArgsPolicy::Error => f.write_str("Error"),
+            ExcessArgsPolicy::Warn(_) => f.write_str("Warn(..)"),
+        }
+    }
+}
+
+/// Specifies how integers which do not fit into requested C type are treated.
+///
+/// Type is determined by length modifier and conversion specifier, e.g. `%hhd` means
+/// `signed char` and `%u` means `unsigned int`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Return [`FormatToError::NumOverflow`]. This is default.
+    ///
+    /// [`FormatToError::NumOverflow`]: ./enum.FormatToError.html#variant.NumOverflow
+    #[default]
+    Error,
+    /// Convert value as C cast does: truncate it to type width and reinterpret as signed or
+    /// unsigned. E.g. `300` is printed as `44` by `%hhd`, and `-1` as `4294967295` by `%u`.
+    Wrap,
+    /// Clamp value to type range. E.g. `300` is printed as `127` by `%hhd`,
+    /// and `-1` as `0` by `%u`.
+    Saturate,
+}
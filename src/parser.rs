@@ -1,7 +1,7 @@
 //! Basic lexing
 //! Refer to `man printf(3)` for details about format string format
 
-use crate::visit::{ConversionSpecification, FormatStringVisitor};
+use crate::visit::{ConversionSpecification, FormatStringVisitor, ParseConfig};
 
 /// Conversion specification
 /// Fields contain position in string, where corresponding item begins
@@ -38,12 +38,18 @@ fn is_length_modifier(c: u8) -> bool {
     b"hlqLjzZt".contains(&c)
 }
 
-pub(crate) fn do_visit(s: &[u8], mut vis: impl FormatStringVisitor) {
+/// Visits items of `s`, starting at position `start`, which must be start of some item
+pub(crate) fn do_visit(
+    s: &[u8],
+    start: usize,
+    config: &ParseConfig,
+    mut vis: impl FormatStringVisitor,
+) {
     let mut state = State::None;
 
     let n = s.len();
     // index of next char to look at
-    let mut i = 0;
+    let mut i = start;
     while i < n {
         let on_percent = s[i] == b'%';
         match state {
@@ -56,7 +62,7 @@ pub(crate) fn do_visit(s: &[u8], mut vis: impl FormatStringVisitor) {
             }
             State::String(j) => {
                 if on_percent {
-                    vis.visit_bytes(&s[j..i]);
+                    vis.visit_literal(j..i, &s[j..i]);
                     state = State::Percent;
                 }
                 // else - do nothing, string continues
@@ -71,8 +77,8 @@ pub(crate) fn do_visit(s: &[u8], mut vis: impl FormatStringVisitor) {
 
                     /* TODO: We want parse spec as loosely as possible, to allow user define
                        custom flags, specifiers etc. This isn't easy task, because of ambiguity
-                       between flags and length modifiers, so probably some ParseConfig struct
-                       should be provided
+                       between flags and length modifiers, so probably `ParseConfig` should be
+                       extended for this
                     */
                     let mut spec = RawSpec {
                         flags: 0,
@@ -86,18 +92,13 @@ pub(crate) fn do_visit(s: &[u8], mut vis: impl FormatStringVisitor) {
                     i -= 1;
                     loop {
                         i += 1;
-                        let ch = if i < n {
-                            s[i]
-                        } else {
-                            // this allows easily handle EOF:
-                            // \0 will be recognized as conversion specification end
-                            b'\0'
-                        };
-                        let maybe_flag = is_flag(ch);
-                        let maybe_field_width = ch.is_ascii_digit();
-                        let maybe_precision = ch == b'.' || ch.is_ascii_digit();
-                        let maybe_length_modifier = is_length_modifier(ch);
-                        let maybe_conv_spec = is_conversion_specifier(ch);
+                        // `None` means EOF, which terminates specification
+                        let ch = s.get(i).copied();
+                        let maybe_flag = ch.is_some_and(is_flag);
+                        let maybe_field_width = ch.is_some_and(|c| c.is_ascii_digit());
+                        let maybe_precision = ch.is_some_and(|c| c == b'.' || c.is_ascii_digit());
+                        let maybe_length_modifier = ch.is_some_and(is_length_modifier);
+                        let maybe_conv_spec = ch.is_some_and(is_conversion_specifier);
                         if spec.field_width == 0 {
                             // we are still parsing flags
                             if maybe_flag {
@@ -139,16 +140,24 @@ pub(crate) fn do_visit(s: &[u8], mut vis: impl FormatStringVisitor) {
                         }
                         if spec.end == 0 {
                             // we are parsing conversion specifier
-                            if maybe_conv_spec {
+                            let first = i == spec.conv_spec;
+                            if maybe_conv_spec && (first || !config.strict) {
                                 // ok, continue eating conversion specifier
+                                // (in strict mode it is exactly one byte)
                                 continue;
-                            } else {
-                                spec.end = i;
-                                break;
                             }
+                            if first && ch.is_some_and(|c| config.strict || c != b'%') {
+                                // unknown byte is still eaten as specifier, so that it is
+                                // reported instead of being silently treated as string.
+                                // `%` is left to start next specification in non-strict mode
+                                i += 1;
+                            }
+                            spec.end = i;
+                            break;
                         }
                     }
                     let vis_spec = ConversionSpecification {
+                        span: spec.flags - 1..spec.end,
                         flags: &s[spec.flags..spec.field_width],
                         field_width: &s[spec.field_width..spec.precision],
                         precision: &s[spec.precision..spec.length],
@@ -156,18 +165,29 @@ pub(crate) fn do_visit(s: &[u8], mut vis: impl FormatStringVisitor) {
                         specifier: &s[spec.conv_spec..spec.end],
                     };
                     vis.visit_specification(vis_spec);
-                    state = if i == n {
-                        State::None
-                    } else {
-                        State::String(i)
-                    }
+                    state = State::None;
+                    // `i` already points to first byte after specification, which can
+                    // start next specification
+                    continue;
                 }
             }
         }
 
         i += 1;
     }
-    if let State::String(j) = state {
-        vis.visit_bytes(&s[j..]);
+    match state {
+        State::String(j) => vis.visit_literal(j..n, &s[j..]),
+        State::Percent if config.strict => {
+            // dangling percent is reported as specification, so it can be rejected
+            vis.visit_specification(ConversionSpecification {
+                span: n - 1..n,
+                flags: &s[n..],
+                field_width: &s[n..],
+                precision: &s[n..],
+                length: &s[n..],
+                specifier: &s[n..],
+            });
+        }
+        _ => {}
     }
 }
@@ -0,0 +1,326 @@
+//! Ring buffer which keeps most recent records
+
+use crate::{format_to_with, BinSink, FormatOptions, FormatToError, Value};
+use core::convert::Infallible;
+
+/// Size of record header, which contains record length
+const HEADER_LEN: usize = 4;
+
+/// Sink which keeps most recent records in caller-provided buffer, overwriting oldest ones.
+///
+/// Data written to sink is appended to current record, which is completed by
+/// [`end_record`]; [`push`] formats a whole record. Records are stored with 4-byte header
+/// each. Record which does not fit into empty buffer is truncated.
+///
+/// ```rust
+/// use formatf::{RingSink, Value};
+/// let mut buf = [0u8; 32];
+/// let mut ring = RingSink::new(&mut buf);
+/// for i in 0..5 {
+///     ring.push(b"event %d;", &[Value::Int(i)]).unwrap();
+/// }
+/// // each record takes 12 bytes, so only last two are retained
+/// let mut out = [0u8; 32];
+/// let mut sink = formatf::CursorSink::new(&mut out);
+/// ring.dump(&mut sink).unwrap();
+/// assert_eq!(sink.as_bytes(), b"event 3;event 4;");
+/// assert_eq!(ring.evicted(), 3);
+/// ```
+///
+/// [`end_record`]: #method.end_record
+/// [`push`]: #method.push
+pub struct RingSink<'a> {
+    buf: &'a mut [u8],
+    /// Position of oldest record
+    head: usize,
+    /// Number of bytes used by completed records, including headers
+    used: usize,
+    /// Number of completed records
+    records: usize,
+    /// Length of current record, if it is started
+    current: Option<usize>,
+    /// Number of records which were overwritten
+    evicted: usize,
+}
+
+impl<'a> RingSink<'a> {
+    /// Creates empty ring over `buf`
+    pub fn new(buf: &'a mut [u8]) -> Self {
+        RingSink {
+            buf,
+            head: 0,
+            used: 0,
+            records: 0,
+            current: None,
+            evicted: 0,
+        }
+    }
+
+    /// Formats `template` with given `args` as new record, which is formed even if output
+    /// is empty.
+    ///
+    /// Returns length of record, which is less than length of output if record was
+    /// truncated to fit into buffer.
+    ///
+    /// Incomplete record written directly to sink, if any, is discarded first.
+    /// On error, incomplete record is discarded. Older records may be overwritten by then,
+    /// unless `FormatOptions::atomic` is used (see [`push_with`]).
+    ///
+    /// [`push_with`]: #method.push_with
+    pub fn push(
+        &mut self,
+        template: &[u8],
+        args: &[Value],
+    ) -> Result<usize, FormatToError<Infallible>> {
+        self.push_with(template, args, &FormatOptions::default())
+    }
+
+    /// Same as [`push`], but uses given `options`
+    ///
+    /// [`push`]: #method.push
+    pub fn push_with(
+        &mut self,
+        template: &[u8],
+        args: &[Value],
+        options: &FormatOptions,
+    ) -> Result<usize, FormatToError<Infallible>> {
+        self.discard_record();
+        self.start_record();
+        match format_to_with(template, args, self, options) {
+            Ok(_) => {
+                let len = self.current.unwrap_or(0);
+                self.end_record();
+                Ok(len)
+            }
+            Err(err) => {
+                self.discard_record();
+                Err(err)
+            }
+        }
+    }
+
+    /// Completes current record. Does nothing if nothing was written since previous call
+    pub fn end_record(&mut self) {
+        let len = match self.current.take() {
+            Some(len) => len,
+            None => return,
+        };
+        // empty record has not reserved space for its header yet
+        self.reserve(0);
+        let start = self.tail();
+        self.write_at(start, &(len as u32).to_le_bytes());
+        self.used += HEADER_LEN + len;
+        self.records += 1;
+    }
+
+    /// Starts empty record, unless it is already started
+    fn start_record(&mut self) {
+        if self.current.is_none() && self.buf.len() >= HEADER_LEN {
+            self.current = Some(0);
+        }
+    }
+
+    /// Discards data written since last `end_record` call
+    pub fn discard_record(&mut self) {
+        self.current = None;
+    }
+
+    /// Returns number of retained records, not including incomplete one
+    pub fn len(&self) -> usize {
+        self.records
+    }
+
+    /// Returns `true` if there are no retained records
+    pub fn is_empty(&self) -> bool {
+        self.records == 0
+    }
+
+    /// Returns number of records which were overwritten
+    pub fn evicted(&self) -> usize {
+        self.evicted
+    }
+
+    /// Removes all records
+    pub fn clear(&mut self) {
+        self.head = 0;
+        self.used = 0;
+        self.records = 0;
+        self.current = None;
+    }
+
+    /// Returns iterator over retained records, from oldest to newest.
+    ///
+    /// Record may wrap around the end of buffer, so it is returned as two slices,
+    /// second of which is usually empty.
+    pub fn iter(&self) -> Records<'_> {
+        Records {
+            buf: self.buf,
+            pos: self.head,
+            left: self.records,
+        }
+    }
+
+    /// Writes retained records to `sink`, from oldest to newest, without separators.
+    ///
+    /// Returns number of bytes written
+    pub fn dump<H: BinSink>(&self, sink: &mut H) -> Result<usize, H::Err> {
+        let mut written = 0;
+        for (a, b) in self.iter() {
+            sink.put_vectored(&[a, b])?;
+            written += a.len() + b.len();
+        }
+        Ok(written)
+    }
+
+    /// Position after last completed record, where current record starts
+    fn tail(&self) -> usize {
+        (self.head + self.used) % self.buf.len()
+    }
+
+    /// Copies `data` to ring at position `pos`
+    fn write_at(&mut self, pos: usize, data: &[u8]) {
+        let first = core::cmp::min(data.len(), self.buf.len() - pos);
+        self.buf[pos..pos + first].copy_from_slice(&data[..first]);
+        self.buf[..data.len() - first].copy_from_slice(&data[first..]);
+    }
+
+    /// Overwrites oldest record
+    fn evict(&mut self) {
+        let len = record_len(self.buf, self.head);
+        self.head = (self.head + HEADER_LEN + len) % self.buf.len();
+        self.used -= HEADER_LEN + len;
+        self.records -= 1;
+        self.evicted += 1;
+    }
+
+    /// Makes space for `len` more bytes of current record, returning number of bytes which
+    /// can be written
+    fn reserve(&mut self, len: usize) -> usize {
+        let current = self.current.unwrap_or(0);
+        let max_len = core::cmp::min(self.buf.len().saturating_sub(HEADER_LEN), u32::MAX as usize);
+        let len = core::cmp::min(len, max_len.saturating_sub(current));
+        // position of current record does not change, since evicting advances `head` and
+        // reduces `used` by the same amount
+        while self.records != 0 && self.buf.len() - self.used < HEADER_LEN + current + len {
+            self.evict();
+        }
+        len
+    }
+}
+
+/// Reads length of record which starts at `pos`
+fn record_len(buf: &[u8], pos: usize) -> usize {
+    let mut header = [0; HEADER_LEN];
+    for (i, byte) in header.iter_mut().enumerate() {
+        *byte = buf[(pos + i) % buf.len()];
+    }
+    u32::from_le_bytes(header) as usize
+}
+
+impl BinSink for RingSink<'_> {
+    type Err = Infallible;
+
+    fn put(&mut self, data: &[u8]) -> Result<(), Self::Err> {
+        if self.buf.len() < HEADER_LEN {
+            return Ok(());
+        }
+        let len = self.reserve(data.len());
+        let current = self.current.unwrap_or(0);
+        let pos = (self.tail() + HEADER_LEN + current) % self.buf.len();
+        self.write_at(pos, &data[..len]);
+        self.current = Some(current + len);
+        Ok(())
+    }
+}
+
+/// Iterator over records of [`RingSink`]
+///
+/// [`RingSink`]: ./struct.RingSink.html
+pub struct Records<'a> {
+    buf: &'a [u8],
+    pos: usize,
+    left: usize,
+}
+
+impl<'a> Iterator for Records<'a> {
+    type Item = (&'a [u8], &'a [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.left == 0 {
+            return None;
+        }
+        self.left -= 1;
+        let len = record_len(self.buf, self.pos);
+        let start = (self.pos + HEADER_LEN) % self.buf.len();
+        self.pos = (start + len) % self.buf.len();
+        let first = core::cmp::min(len, self.buf.len() - start);
+        Some((&self.buf[start..start + first], &self.buf[..len - first]))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.left, Some(self.left))
+    }
+}
+
+impl ExactSizeIterator for Records<'_> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn records(ring: &RingSink) -> Vec<Vec<u8>> {
+        ring.iter().map(|(a, b)| [a, b].concat()).collect()
+    }
+
+    #[test]
+    fn wraps() {
+        let mut buf = [0u8; 20];
+        let mut ring = RingSink::new(&mut buf);
+        ring.push(b"%s", &[Value::String(b"abc")]).unwrap();
+        ring.push(b"%d", &[Value::Int(12345)]).unwrap();
+        assert_eq!(records(&ring), [&b"abc"[..], b"12345"]);
+
+        // wraps around the end of buffer, evicting first record
+        ring.push(b"%6d", &[Value::Int(7)]).unwrap();
+        assert_eq!(records(&ring), [&b"12345"[..], b"     7"]);
+        assert_eq!(ring.evicted(), 1);
+
+        // failed record is discarded
+        assert!(ring.push(b"%d", &[]).is_err());
+        assert_eq!(ring.len(), 2);
+
+        // too long record is truncated and replaces everything
+        let len = ring
+            .push(b"%s", &[Value::String(b"0123456789abcdefghij")])
+            .unwrap();
+        assert_eq!(len, 16);
+        assert_eq!(records(&ring), [&b"0123456789abcdef"[..]]);
+        assert_eq!(ring.evicted(), 3);
+
+        // data written directly forms record once it is ended
+        ring.put(b"x").unwrap();
+        ring.put(b"y").unwrap();
+        assert_eq!((ring.len(), ring.evicted()), (0, 4));
+        ring.end_record();
+        ring.end_record();
+        assert_eq!(records(&ring), [&b"xy"[..]]);
+
+        // empty messages form records too
+        assert_eq!(ring.push(b"", &[]).unwrap(), 0);
+        ring.put(b"").unwrap();
+        ring.end_record();
+        assert_eq!(records(&ring), [&b"xy"[..], b"", b""]);
+        ring.push(b"%s", &[Value::String(b"0123456789")]).unwrap();
+        assert_eq!(records(&ring), [&b""[..], b"0123456789"]);
+    }
+
+    #[test]
+    fn tiny_buffer() {
+        let mut buf = [0u8; 3];
+        let mut ring = RingSink::new(&mut buf);
+        assert_eq!(ring.push(b"abc", &[]).unwrap(), 0);
+        ring.end_record();
+        assert!(ring.is_empty());
+        assert_eq!(ring.iter().count(), 0);
+    }
+}
@@ -0,0 +1,235 @@
+//! Rewriting of untrusted templates into safe ones
+
+use crate::{
+    format::until_nul,
+    limits::parse_decimal,
+    visit::{self, ConversionSpecification, FormatStringVisitor},
+    FormatOptions, FormatToError,
+};
+use core::{convert::Infallible, ops::Range};
+
+/// Describes single change made by [`sanitize`]
+///
+/// [`sanitize`]: ./fn.sanitize.html
+#[derive(Debug)]
+pub struct SanitizeChange {
+    /// Position of changed specification in original template
+    pub span: Range<usize>,
+    pub kind: SanitizeChangeKind,
+}
+
+#[derive(Debug)]
+pub enum SanitizeChangeKind {
+    /// Specification was escaped (i.e. will be printed as is), because it would cause
+    /// given error
+    Escaped(FormatToError<Infallible>),
+    /// Field width or precision was reduced to `max_width`
+    Clamped,
+}
+
+/// Result of [`sanitize`]
+///
+/// [`sanitize`]: ./fn.sanitize.html
+#[derive(Debug)]
+pub struct Sanitized {
+    /// Rewritten template
+    pub template: Vec<u8>,
+    /// All changes, in order of occurence
+    pub changes: Vec<SanitizeChange>,
+}
+
+/// Rewrites `template` so that it can be used with `options` without errors caused by
+/// format string itself.
+///
+/// - Malformed and unsupported specifications, specifications forbidden by
+///   `allowed_specifiers` or `allowed_flags`, and specifications above `max_specs`
+///   are escaped, i.e. `%` is replaced with `%%`.
+/// - Field width and precision above `max_width` are clamped.
+/// - Trailing `%` is escaped.
+///
+/// Errors which depend on arguments (such as type mismatch or `max_output`) can still occur.
+///
+/// ```rust
+/// use formatf::{sanitize, FormatOptions};
+/// let options = FormatOptions::untrusted();
+/// let res = sanitize(b"%n%5000d%s%", &options);
+/// assert_eq!(res.template, b"%%n%256d%s%%");
+/// assert_eq!(res.changes.len(), 3);
+/// ```
+pub fn sanitize(template: &[u8], options: &FormatOptions) -> Sanitized {
+    let template = if options.c_strings {
+        until_nul(template)
+    } else {
+        template
+    };
+    let mut sanitizer = Sanitizer {
+        template,
+        options,
+        output: Vec::with_capacity(template.len()),
+        changes: Vec::new(),
+        specs: 0,
+        pos: 0,
+    };
+    visit::visit_with(template, &options.parse_config(), &mut sanitizer);
+    if sanitizer.pos < template.len() {
+        // parser drops trailing `%` in non-strict mode
+        sanitizer.escape(sanitizer.pos..template.len(), FormatToError::Invalid);
+    }
+    Sanitized {
+        template: sanitizer.output,
+        changes: sanitizer.changes,
+    }
+}
+
+struct Sanitizer<'a> {
+    template: &'a [u8],
+    options: &'a FormatOptions<'a>,
+    output: Vec<u8>,
+    changes: Vec<SanitizeChange>,
+    /// Number of specifications kept in output
+    specs: usize,
+    /// Number of processed template bytes
+    pos: usize,
+}
+
+impl Sanitizer<'_> {
+    fn escape(&mut self, span: Range<usize>, err: FormatToError<Infallible>) {
+        for &c in &self.template[span.clone()] {
+            if c == b'%' {
+                self.output.push(b'%');
+            }
+            self.output.push(c);
+        }
+        self.changes.push(SanitizeChange {
+            span,
+            kind: SanitizeChangeKind::Escaped(err),
+        });
+    }
+
+    /// Checks specification, returning error which it would cause
+    fn check(&self, spec: ConversionSpecification) -> Result<(), FormatToError<Infallible>> {
+        let options = self.options;
+        if options.max_specs.is_some_and(|max| self.specs >= max) {
+            return Err(FormatToError::TooManySpecs);
+        }
+        if !options.is_allowed(&spec) {
+            return Err(FormatToError::Forbidden);
+        }
+        match options.parse_spec(spec) {
+            Ok(parsed) if !parsed.is_supported(options.locale.is_some()) => {
+                Err(FormatToError::Unsupported)
+            }
+            Ok(_) => Ok(()),
+            Err(e) => Err(FormatToError::Spec(e)),
+        }
+    }
+
+    /// Writes specification with width and precision clamped to `max_width`.
+    ///
+    /// Returns whether anything was changed.
+    fn write_clamped(&mut self, spec: &ConversionSpecification) -> bool {
+        let max_width = match self.options.max_width {
+            Some(max_width) => max_width,
+            None => usize::MAX,
+        };
+        let width = parse_decimal(spec.field_width);
+        let prec = parse_decimal(spec.precision.get(1..).unwrap_or_default());
+        self.output.push(b'%');
+        self.output.extend_from_slice(spec.flags);
+        let mut buf = itoa::Buffer::new();
+        if width > max_width {
+            self.output
+                .extend_from_slice(buf.format(max_width).as_bytes());
+        } else {
+            self.output.extend_from_slice(spec.field_width);
+        }
+        if prec > max_width {
+            self.output.push(b'.');
+            self.output
+                .extend_from_slice(buf.format(max_width).as_bytes());
+        } else {
+            self.output.extend_from_slice(spec.precision);
+        }
+        self.output.extend_from_slice(spec.length);
+        self.output.extend_from_slice(spec.specifier);
+        width > max_width || prec > max_width
+    }
+}
+
+impl FormatStringVisitor for Sanitizer<'_> {
+    fn visit_bytes(&mut self, b: &[u8]) {
+        self.output.extend_from_slice(b);
+        self.pos += b.len();
+    }
+
+    fn visit_escaped_percent(&mut self) {
+        self.output.extend_from_slice(b"%%");
+        self.pos += 2;
+    }
+
+    fn visit_specification(&mut self, spec: ConversionSpecification) {
+        let span = spec.span.clone();
+        self.pos = span.end;
+        if let Err(err) = self.check(spec.clone()) {
+            self.escape(span, err);
+            return;
+        }
+        self.specs += 1;
+        if self.write_clamped(&spec) {
+            self.changes.push(SanitizeChange {
+                span,
+                kind: SanitizeChangeKind::Clamped,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{format_with, high::ParseError, Value};
+
+    fn check(template: &[u8], options: &FormatOptions, expected: &[u8]) -> Vec<SanitizeChange> {
+        let res = sanitize(template, options);
+        assert_eq!(res.template, expected);
+        // sanitized template does not cause errors by itself
+        const ONE: Value = Value::Int(1);
+        let args = [ONE; 100];
+        let options = options.clone().max_output(None);
+        format_with(&res.template, &args, &options).unwrap();
+        res.changes
+    }
+
+    #[test]
+    fn escapes() {
+        let changes = check(b"%d%y%5%", &FormatOptions::new(), b"%d%%y%%5%%");
+        let spans: Vec<_> = changes.iter().map(|ch| ch.span.clone()).collect();
+        assert_eq!(spans, [2..4, 4..6, 6..7]);
+        assert!(matches!(
+            changes[0].kind,
+            SanitizeChangeKind::Escaped(FormatToError::Spec(ParseError::UnknownSpecifier))
+        ));
+
+        let strict = FormatOptions::new().strict(true);
+        check(b"%.5.3d|%hhs|%5%|%", &strict, b"%%.5.3d|%%hhs|%%5%%|%%");
+    }
+
+    #[test]
+    fn limits() {
+        let options = FormatOptions::new()
+            .max_specs(Some(2))
+            .max_width(Some(10))
+            .allowed_flags(Some(b"-"));
+        let changes = check(b"%-20.30d %011d %d %d", &options, b"%-10.10d %%011d %d %%d");
+        let kinds: Vec<_> = changes
+            .iter()
+            .map(|ch| match ch.kind {
+                SanitizeChangeKind::Clamped => "clamped",
+                SanitizeChangeKind::Escaped(FormatToError::Forbidden) => "forbidden",
+                SanitizeChangeKind::Escaped(FormatToError::TooManySpecs) => "too many",
+                _ => "other",
+            })
+            .collect();
+        assert_eq!(kinds, ["clamped", "forbidden", "too many"]);
+    }
+}
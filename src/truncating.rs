@@ -0,0 +1,205 @@
+//! `snprintf`-like output to fixed-size buffer
+
+use crate::{format_to_with, BinSink, FormatOptions, FormatToError, Value};
+use core::convert::Infallible;
+
+/// Helper struct that implements BinSink via writing bytes to slice, silently dropping
+/// bytes which do not fit.
+/// This Sink is infallible.
+///
+/// ```rust
+/// use formatf::{format_to, TruncatingSink, Value};
+/// let mut buf = [0xffu8; 8];
+/// let mut sink = TruncatingSink::new(&mut buf).nul_terminated(true);
+/// format_to(b"Hello, %s!", &[Value::String(b"world")], &mut sink).unwrap();
+/// assert_eq!((sink.written(), sink.required()), (7, 13));
+/// assert_eq!(&buf, b"Hello, \0");
+/// ```
+pub struct TruncatingSink<'a> {
+    buf: &'a mut [u8],
+    written: usize,
+    required: usize,
+    truncated: bool,
+    nul_terminated: bool,
+    utf8: bool,
+}
+
+impl<'a> TruncatingSink<'a> {
+    /// Creates sink writing to `buf`, which is neither NUL-terminated nor cut at
+    /// UTF-8 character boundaries
+    pub fn new(buf: &'a mut [u8]) -> Self {
+        TruncatingSink {
+            buf,
+            written: 0,
+            required: 0,
+            truncated: false,
+            nul_terminated: false,
+            utf8: false,
+        }
+    }
+
+    /// If enabled, last byte of buffer is reserved, and written data is always followed
+    /// by NUL byte (unless buffer is empty). Disabled by default
+    pub fn nul_terminated(mut self, nul_terminated: bool) -> Self {
+        self.nul_terminated = nul_terminated;
+        self.terminate();
+        self
+    }
+
+    /// If enabled, output is never truncated in the middle of UTF-8 character, including
+    /// character which is split between several `put` calls. Disabled by default
+    pub fn utf8_boundaries(mut self, utf8: bool) -> Self {
+        self.utf8 = utf8;
+        self
+    }
+
+    /// Returns number of bytes written to buffer, not including NUL terminator
+    pub fn written(&self) -> usize {
+        self.written
+    }
+
+    /// Returns number of bytes which would be written if buffer was large enough,
+    /// not including NUL terminator
+    pub fn required(&self) -> usize {
+        self.required
+    }
+
+    /// Returns `true` if some data was dropped
+    pub fn is_truncated(&self) -> bool {
+        self.truncated
+    }
+
+    /// Returns written part of buffer, not including NUL terminator
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buf[..self.written]
+    }
+
+    fn capacity(&self) -> usize {
+        if self.nul_terminated {
+            self.buf.len().saturating_sub(1)
+        } else {
+            self.buf.len()
+        }
+    }
+
+    /// Drops beginning of character at the end of written data, if it is incomplete
+    fn cut_incomplete_char(&mut self) {
+        let written = &self.buf[..self.written];
+        let tail = written
+            .iter()
+            .rev()
+            .take(3)
+            .take_while(|&&b| is_continuation(b))
+            .count();
+        let Some(lead) = written.len().checked_sub(tail + 1) else {
+            return;
+        };
+        let char_len = match written[lead] {
+            0xf0..=0xff => 4,
+            0xe0..=0xef => 3,
+            0xc0..=0xdf => 2,
+            _ => 1,
+        };
+        if char_len > tail + 1 {
+            self.written = lead;
+        }
+    }
+
+    fn terminate(&mut self) {
+        if self.nul_terminated && self.written < self.buf.len() {
+            self.buf[self.written] = 0;
+        }
+    }
+}
+
+impl BinSink for TruncatingSink<'_> {
+    type Err = Infallible;
+
+    fn put(&mut self, data: &[u8]) -> Result<(), Self::Err> {
+        self.required = self.required.saturating_add(data.len());
+        if self.truncated {
+            // nothing is written after first truncation, so output is always a prefix
+            return Ok(());
+        }
+        let available = self.capacity() - self.written;
+        let mut len = data.len();
+        if len > available {
+            self.truncated = true;
+            len = available;
+            if self.utf8 {
+                // step back to character boundary, i.e. to byte which is not continuation
+                while len > 0 && is_continuation(data[len]) {
+                    len -= 1;
+                }
+                if len == 0 && is_continuation(data[0]) {
+                    self.cut_incomplete_char();
+                }
+            }
+        }
+        self.buf[self.written..self.written + len].copy_from_slice(&data[..len]);
+        self.written += len;
+        self.terminate();
+        Ok(())
+    }
+}
+
+fn is_continuation(byte: u8) -> bool {
+    byte & 0xc0 == 0x80
+}
+
+/// Result of [`snprintf`]
+///
+/// [`snprintf`]: ./fn.snprintf.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Written {
+    /// Number of bytes written, not including NUL terminator
+    pub written: usize,
+    /// Number of bytes which would be written if buffer was large enough,
+    /// not including NUL terminator. This is what C's `snprintf` returns
+    pub required: usize,
+}
+
+impl Written {
+    /// Returns `true` if output did not fit into buffer
+    pub fn is_truncated(&self) -> bool {
+        self.written < self.required
+    }
+}
+
+/// Formats `template` with given `args` into `buf`, like C's `snprintf`.
+///
+/// Output which does not fit is dropped, and `buf` is always NUL-terminated, unless it is
+/// empty. Formatting errors are still reported, in which case `buf` contains output
+/// produced before error.
+///
+/// ```rust
+/// use formatf::{snprintf, Value};
+/// let mut buf = [0u8; 4];
+/// let res = snprintf(&mut buf, b"%d", &[Value::Int(12345)]).unwrap();
+/// assert_eq!((res.written, res.required), (3, 5));
+/// assert_eq!(&buf, b"123\0");
+/// ```
+pub fn snprintf(
+    buf: &mut [u8],
+    template: &[u8],
+    args: &[Value],
+) -> Result<Written, FormatToError<Infallible>> {
+    snprintf_with(buf, template, args, &FormatOptions::default())
+}
+
+/// Same as [`snprintf`], but uses given `options`
+///
+/// [`snprintf`]: ./fn.snprintf.html
+pub fn snprintf_with(
+    buf: &mut [u8],
+    template: &[u8],
+    args: &[Value],
+    options: &FormatOptions,
+) -> Result<Written, FormatToError<Infallible>> {
+    let mut sink = TruncatingSink::new(buf).nul_terminated(true);
+    format_to_with(template, args, &mut sink, options)?;
+    Ok(Written {
+        written: sink.written(),
+        required: sink.required(),
+    })
+}
@@ -0,0 +1,65 @@
+//! Reporting which arguments are used by format string
+
+use crate::{format, CountSink, FormatOptions, Value};
+use core::ops::Range;
+
+/// Describes which conversion specifications consumed which arguments.
+///
+/// Returned by [`args_usage`].
+///
+/// [`args_usage`]: ./fn.args_usage.html
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArgsUsage {
+    /// For each argument, span of specification which consumed it,
+    /// or `None` if argument was never referenced
+    pub consumed_by: Vec<Option<Range<usize>>>,
+}
+
+impl ArgsUsage {
+    /// Returns indices of arguments which were not referenced by format string
+    pub fn unused(&self) -> impl Iterator<Item = usize> + '_ {
+        self.consumed_by
+            .iter()
+            .enumerate()
+            .filter(|(_, spec)| spec.is_none())
+            .map(|(i, _)| i)
+    }
+
+    /// Returns `true` if all arguments were referenced
+    pub fn all_used(&self) -> bool {
+        self.unused().next().is_none()
+    }
+}
+
+/// Reports which arguments would be consumed by which conversion specifications when
+/// formatting `template` with `args`.
+///
+/// Formatting is continued after errors (as in [`format_to_recovering`]), so that whole
+/// template is examined. No output is produced.
+///
+/// ```rust
+/// use formatf::{args_usage, FormatOptions, Value};
+/// let args = [Value::Int(1), Value::Int(2)];
+/// let usage = args_usage(b"x=%d", &args, &FormatOptions::default());
+/// assert_eq!(usage.consumed_by, [Some(2..4), None]);
+/// assert_eq!(usage.unused().collect::<Vec<_>>(), [1]);
+/// ```
+///
+/// [`format_to_recovering`]: ./fn.format_to_recovering.html
+pub fn args_usage(template: &[u8], args: &[Value], options: &FormatOptions) -> ArgsUsage {
+    let mut consumed_by = vec![None; args.len()];
+    let mut on_error = |_, _| {};
+    let mut on_arg = |idx: usize, span: Range<usize>| {
+        if let Some(slot) = consumed_by.get_mut(idx) {
+            *slot = Some(span);
+        }
+    };
+    let hooks = format::Hooks {
+        on_error: Some(&mut on_error),
+        on_arg: Some(&mut on_arg),
+    };
+    // `CountSink` never fails, and other errors are passed to `on_error`, except for
+    // violation of limits, which leaves all arguments unused
+    let _ = format::run(template, args, &mut CountSink(0), options, hooks);
+    ArgsUsage { consumed_by }
+}
@@ -0,0 +1,102 @@
+//! Incremental UTF-8 decoding for sinks which produce text
+
+/// Splits chunked byte stream into valid strings and invalid sequences.
+///
+/// Character split between chunks is buffered until it is complete.
+#[derive(Default)]
+pub(crate) struct Utf8Decoder {
+    /// Beginning of incomplete character from previous chunk
+    pending: [u8; 4],
+    pending_len: usize,
+}
+
+/// Part of decoded data: either valid string, or invalid sequence
+pub(crate) type Piece<'a> = Result<&'a str, &'a [u8]>;
+
+/// Returns length of character with given first byte
+fn char_len(first: u8) -> usize {
+    match first {
+        0xf0..=0xff => 4,
+        0xe0..=0xef => 3,
+        _ => 2,
+    }
+}
+
+impl Utf8Decoder {
+    /// Returns `true` if last chunk ended with incomplete character
+    pub(crate) fn has_pending(&self) -> bool {
+        self.pending_len != 0
+    }
+
+    /// Decodes next chunk, passing pieces to `emit` in order
+    pub(crate) fn decode<E>(
+        &mut self,
+        mut data: &[u8],
+        emit: &mut impl FnMut(Piece) -> Result<(), E>,
+    ) -> Result<(), E> {
+        if self.pending_len != 0 {
+            let prev_len = self.pending_len;
+            let need = char_len(self.pending[0]);
+            let take = core::cmp::min(need - prev_len, data.len());
+            self.pending[prev_len..prev_len + take].copy_from_slice(&data[..take]);
+            self.pending_len += take;
+            let pending = self.pending;
+            let pending = &pending[..self.pending_len];
+            match core::str::from_utf8(pending) {
+                Ok(s) => {
+                    self.pending_len = 0;
+                    data = &data[take..];
+                    emit(Ok(s))?;
+                }
+                // character is still incomplete, so whole `data` was taken
+                Err(e) if e.error_len().is_none() => return Ok(()),
+                Err(e) => {
+                    // bytes after invalid sequence are decoded again as part of `data`
+                    let invalid_len = e.error_len().unwrap_or(pending.len());
+                    self.pending_len = 0;
+                    data = &data[invalid_len - prev_len..];
+                    emit(Err(&pending[..invalid_len]))?;
+                }
+            }
+        }
+        loop {
+            let e = match core::str::from_utf8(data) {
+                Ok(s) => {
+                    if !s.is_empty() {
+                        emit(Ok(s))?;
+                    }
+                    return Ok(());
+                }
+                Err(e) => e,
+            };
+            let (valid, rest) = data.split_at(e.valid_up_to());
+            if !valid.is_empty() {
+                emit(Ok(core::str::from_utf8(valid).unwrap_or_default()))?;
+            }
+            match e.error_len() {
+                Some(invalid_len) => {
+                    emit(Err(&rest[..invalid_len]))?;
+                    data = &rest[invalid_len..];
+                }
+                None => {
+                    self.pending[..rest.len()].copy_from_slice(rest);
+                    self.pending_len = rest.len();
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    /// Reports incomplete character at the end of stream as invalid sequence
+    pub(crate) fn finish<E>(
+        &mut self,
+        emit: &mut impl FnMut(Piece) -> Result<(), E>,
+    ) -> Result<(), E> {
+        if self.pending_len == 0 {
+            return Ok(());
+        }
+        let pending = self.pending;
+        let len = core::mem::take(&mut self.pending_len);
+        emit(Err(&pending[..len]))
+    }
+}
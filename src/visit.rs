@@ -8,8 +8,16 @@
 /// - They do not overlap, but consecutive slices touch themselves
 /// - Next slice is located after previous
 ///
-/// Note that each slice can be empty
+/// Note that each slice can be empty.
+///
+/// Fields may be added in future, so use [`new`] to create specification by hand.
+///
+/// [`new`]: #method.new
+#[derive(Debug, Clone)]
+#[non_exhaustive]
 pub struct ConversionSpecification<'a> {
+    /// Position of whole specification (including leading `%`) in format string
+    pub span: core::ops::Range<usize>,
     pub flags: &'a [u8],
     pub field_width: &'a [u8],
     pub precision: &'a [u8],
@@ -17,12 +25,50 @@ pub struct ConversionSpecification<'a> {
     pub specifier: &'a [u8],
 }
 
+impl<'a> ConversionSpecification<'a> {
+    /// Creates specification from its parts.
+    ///
+    /// `span` is only used to report errors, so it can be arbitrary if specification does
+    /// not come from format string.
+    ///
+    /// ```rust
+    /// use formatf::{high::ParsedConversionSpecification, visit::ConversionSpecification};
+    /// let spec = ConversionSpecification::new(0..4, b"-", b"5", b"", b"", b"d");
+    /// assert!(ParsedConversionSpecification::try_parse(spec).is_ok());
+    /// ```
+    pub fn new(
+        span: core::ops::Range<usize>,
+        flags: &'a [u8],
+        field_width: &'a [u8],
+        precision: &'a [u8],
+        length: &'a [u8],
+        specifier: &'a [u8],
+    ) -> Self {
+        ConversionSpecification {
+            span,
+            flags,
+            field_width,
+            precision,
+            length,
+            specifier,
+        }
+    }
+}
+
 pub trait FormatStringVisitor {
     /// String chunk, taken from format string as is
     fn visit_bytes(&mut self, b: &[u8]) {
         let _ = b;
     }
 
+    /// String chunk, together with its position in format string.
+    ///
+    /// This is what parser calls; default implementation forwards to `visit_bytes`.
+    fn visit_literal(&mut self, span: core::ops::Range<usize>, b: &[u8]) {
+        let _ = span;
+        self.visit_bytes(b);
+    }
+
     /// Called when escaped percent (`%%`) is found
     fn visit_escaped_percent(&mut self) {
         self.visit_bytes(b"%");
@@ -42,6 +88,10 @@ impl<T: FormatStringVisitor> FormatStringVisitor for &mut T {
         (**self).visit_bytes(b);
     }
 
+    fn visit_literal(&mut self, span: core::ops::Range<usize>, b: &[u8]) {
+        (**self).visit_literal(span, b);
+    }
+
     fn visit_escaped_percent(&mut self) {
         (**self).visit_escaped_percent();
     }
@@ -55,8 +105,35 @@ impl<T: FormatStringVisitor> FormatStringVisitor for &mut T {
     }
 }
 
+/// Settings which affect how format string is split into parts.
+///
+/// By default conversion specifier is the longest run of known specifier bytes. If there is
+/// no such byte, next byte (whatever it is, including NUL) is taken as specifier, so that
+/// specification like `%y` can be reported as unknown instead of being partially copied to
+/// output. Only `%`, which starts next specification, and end of format string leave
+/// specifier empty.
+#[derive(Debug, Clone, Default)]
+pub struct ParseConfig {
+    /// Split format string according to C99/POSIX grammar exactly.
+    ///
+    /// In this mode conversion specifier always consists of exactly one byte (even if this
+    /// byte is not a known specifier), and `%` at the end of format string is reported as
+    /// specification with all parts empty. This makes all malformed specifications visible to
+    /// visitor, which can validate them with [`ParsedConversionSpecification::try_parse_strict`].
+    ///
+    /// [`ParsedConversionSpecification::try_parse_strict`]: ../high/struct.ParsedConversionSpecification.html#method.try_parse_strict
+    pub strict: bool,
+}
+
 pub fn visit(s: &[u8], vis: impl FormatStringVisitor) {
-    crate::parser::do_visit(s, vis);
+    visit_with(s, &ParseConfig::default(), vis);
+}
+
+/// Same as [`visit`], but allows to customize parsing
+///
+/// [`visit`]: ./fn.visit.html
+pub fn visit_with(s: &[u8], config: &ParseConfig, vis: impl FormatStringVisitor) {
+    crate::parser::do_visit(s, 0, config, vis);
 }
 
 #[cfg(test)]
@@ -110,17 +187,24 @@ mod tests {
         }
     }
 
-    fn visit_to_events(s: &[u8]) -> Vec<Event> {
+    fn visit_to_events(s: &[u8], config: &ParseConfig) -> Vec<Event> {
         let mut vis: CollectVisitor = Default::default();
-        visit(s, &mut vis);
+        visit_with(s, config, &mut vis);
         vis.data
     }
 
     mod parsing {
-        use super::{visit_to_events, Event, OwnedConversionSpecification};
+        use super::{visit_to_events, Event, OwnedConversionSpecification, ParseConfig};
+        use crate::visit::{visit, FormatStringVisitor};
 
         fn check(s: &[u8], expected: &[Event]) {
-            let actual = visit_to_events(s);
+            let actual = visit_to_events(s, &ParseConfig::default());
+            assert_eq!(actual, expected);
+        }
+
+        fn check_strict(s: &[u8], expected: &[Event]) {
+            let config = ParseConfig { strict: true };
+            let actual = visit_to_events(s, &config);
             assert_eq!(actual, expected);
         }
 
@@ -174,5 +258,91 @@ mod tests {
                 ],
             )
         }
+
+        #[test]
+        fn adjacent_specs() {
+            let spec_d = || {
+                Event::ConvSpec(OwnedConversionSpecification {
+                    specifier: b"d".to_vec(),
+                    ..Default::default()
+                })
+            };
+            // byte which ends specification used to be treated as literal, so second
+            // specification was not recognized
+            check(b"%d%d", &[spec_d(), spec_d()]);
+            check(b"%d%%", &[spec_d(), Event::Percent]);
+        }
+
+        #[test]
+        fn unknown_specifier() {
+            let spec = |specifier: &[u8]| {
+                Event::ConvSpec(OwnedConversionSpecification {
+                    flags: b"-".to_vec(),
+                    specifier: specifier.to_vec(),
+                    ..Default::default()
+                })
+            };
+            check(b"%-yz", &[spec(b"y"), Event::String(b"z".to_vec())]);
+            check(b"%-\0%-", &[spec(b"\0"), spec(b"")]);
+        }
+
+        #[test]
+        fn percent_after_prefix() {
+            let spec = |field_width: &[u8], specifier: &[u8]| {
+                Event::ConvSpec(OwnedConversionSpecification {
+                    field_width: field_width.to_vec(),
+                    specifier: specifier.to_vec(),
+                    ..Default::default()
+                })
+            };
+            // `%` used to be taken as unknown specifier, hiding specification after it:
+            // `%5%d` was split into `%5%` and literal `d`
+            check(b"%5%d", &[spec(b"5", b""), spec(b"", b"d")]);
+            check(b"%5%%", &[spec(b"5", b""), Event::Percent]);
+            // strict grammar still takes exactly one byte as specifier
+            check_strict(b"%5%d", &[spec(b"5", b"%"), Event::String(b"d".to_vec())]);
+        }
+
+        #[test]
+        fn literal_spans() {
+            struct Spans(Vec<core::ops::Range<usize>>);
+            impl FormatStringVisitor for Spans {
+                fn visit_literal(&mut self, span: core::ops::Range<usize>, b: &[u8]) {
+                    assert_eq!(span.len(), b.len());
+                    self.0.push(span);
+                }
+            }
+            let mut spans = Spans(Vec::new());
+            visit(b"ab%d%%c%s|de", &mut spans);
+            assert_eq!(spans.0, [0..2, 6..7, 9..12]);
+        }
+
+        #[test]
+        fn strict() {
+            check_strict(
+                b"%sabc%.5.3d%",
+                &[
+                    Event::ConvSpec(OwnedConversionSpecification {
+                        specifier: b"s".to_vec(),
+                        ..Default::default()
+                    }),
+                    Event::String(b"abc".to_vec()),
+                    Event::ConvSpec(OwnedConversionSpecification {
+                        precision: b".5.3".to_vec(),
+                        specifier: b"d".to_vec(),
+                        ..Default::default()
+                    }),
+                    Event::ConvSpec(OwnedConversionSpecification::default()),
+                ],
+            );
+            check_strict(
+                b"%-k",
+                &[Event::ConvSpec(OwnedConversionSpecification {
+                    flags: b"-".to_vec(),
+                    specifier: b"k".to_vec(),
+                    ..Default::default()
+                })],
+            );
+        }
     }
 }
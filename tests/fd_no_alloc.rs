@@ -0,0 +1,57 @@
+//! Checks that formatting to `FdSink` does not allocate.
+//!
+//! This is separate test binary, because it replaces global allocator.
+#![cfg(target_os = "linux")]
+
+use formatf::{format_to, FdSink, Value};
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    cell::Cell,
+};
+
+/// Allocator which counts allocations made by current thread
+struct CountingAlloc;
+
+std::thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAlloc = CountingAlloc;
+
+#[test]
+fn fd_sink_does_not_allocate() {
+    let mut fds = [0; 2];
+    // SAFETY: `fds` has space for two descriptors
+    assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+    let mut sink = FdSink::new(fds[1]);
+    let args = [
+        Value::String(b"SIGSEGV"),
+        Value::Int(11),
+        Value::Int(-1),
+        Value::String(b"x"),
+    ];
+
+    let before = ALLOCATIONS.with(Cell::get);
+    let written = format_to(b"%s (%d) at %5d;%-70s|\n", &args, &mut sink);
+    let after = ALLOCATIONS.with(Cell::get);
+    assert_eq!(before, after);
+    assert_eq!(written.unwrap(), 94);
+
+    // SAFETY: closing descriptors created above
+    unsafe {
+        libc::close(fds[0]);
+        libc::close(fds[1]);
+    }
+}